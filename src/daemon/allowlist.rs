@@ -0,0 +1,134 @@
+use super::errors::{Result, WatchError};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::sync::RwLock;
+use std::time::Duration;
+use log::{info, warn};
+
+/// GitHub's list of the addresses its hooks are delivered from
+const GITHUB_META_URL: &str = "https://api.github.com/meta";
+/// How often GitHub's hook ranges are fetched again
+const GITHUB_REFRESH: Duration = Duration::from_secs(24 * 60 * 60);
+/// Retry delay after fetching them failed
+const GITHUB_RETRY: Duration = Duration::from_secs(5 * 60);
+
+/// An address range such as "10.0.0.0/8" or "2001:db8::/32"; a bare address is a single host
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(text: String) -> std::result::Result<Cidr, String> {
+        let invalid = || format!("invalid address range '{text}', expected e.g. \"192.0.2.0/24\"");
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (text.as_str(), None),
+        };
+        let network: IpAddr = address.trim().parse().map_err(|_| invalid())?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|prefix| *prefix <= bits).ok_or_else(invalid)?,
+            None => bits,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        // Clients on an IPv4 socket of a dual-stack listener show up as ::ffff:a.b.c.d
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                same_prefix(u32::from(network).into(), u32::from(address).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                same_prefix(u128::from(network), u128::from(address), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn same_prefix(network: u128, address: u128, bits: u8, prefix: u8) -> bool {
+    let host_bits = u32::from(bits - prefix);
+    (network ^ address).checked_shr(host_bits).unwrap_or(0) == 0
+}
+
+/// Addresses webhook deliveries are accepted from: the configured ranges plus,
+/// when asked for, the ones GitHub publishes for its hooks
+pub struct Allowlist {
+    ranges: Vec<Cidr>,
+    github: Option<RwLock<Vec<Cidr>>>,
+}
+
+impl Allowlist {
+    pub fn new(ranges: Vec<Cidr>, github_hooks: bool) -> Allowlist {
+        Allowlist { ranges, github: github_hooks.then(RwLock::default) }
+    }
+
+    /// Whether anything is configured; an empty allowlist lets every address in
+    pub fn is_enabled(&self) -> bool {
+        !self.ranges.is_empty() || self.github.is_some()
+    }
+
+    pub fn allows(&self, address: IpAddr) -> bool {
+        if !self.is_enabled() || self.ranges.iter().any(|range| range.contains(address)) {
+            return true;
+        }
+        self.github
+            .as_ref()
+            .is_some_and(|github| github.read().unwrap().iter().any(|range| range.contains(address)))
+    }
+
+    /// Keep GitHub's hook ranges current, fetching them now and once a day.
+    /// A failed fetch keeps the previous list and is retried sooner.
+    pub async fn refresh_github(&self) {
+        let Some(github) = &self.github else {
+            return;
+        };
+        loop {
+            let fetched = tokio::task::spawn_blocking(fetch_github_hooks).await.map_err(WatchError::from);
+            let delay = match fetched.and_then(|ranges| ranges) {
+                Ok(ranges) => {
+                    info!("Accepting GitHub webhooks from {} published address ranges", ranges.len());
+                    *github.write().unwrap() = ranges;
+                    GITHUB_REFRESH
+                }
+                Err(e) => {
+                    warn!("Could not fetch GitHub's hook address ranges: {}", e);
+                    GITHUB_RETRY
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GithubMeta {
+    hooks: Vec<Cidr>,
+}
+
+fn fetch_github_hooks() -> Result<Vec<Cidr>> {
+    let output = Command::new("curl")
+        .args(["-fsS", "-m", "10", "-H", "Accept: application/vnd.github+json", GITHUB_META_URL])
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(WatchError::CommandFailed {
+            command: format!("curl {GITHUB_META_URL}"),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    let meta: GithubMeta = serde_json::from_slice(&output.stdout).map_err(|e| WatchError::CommandFailed {
+        command: format!("curl {GITHUB_META_URL}"),
+        stderr: format!("unexpected response: {e}"),
+    })?;
+    Ok(meta.hooks)
+}
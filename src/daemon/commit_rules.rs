@@ -0,0 +1,53 @@
+use super::glob::glob_match;
+use super::repo_config::{CommitAction, CommitRule};
+
+/// Labels a commit message carries for rule matching:
+/// "type(scope)", "type", and "BREAKING CHANGE" for breaking commits
+fn labels(message: &str) -> Vec<String> {
+    let header = message.lines().next().unwrap_or_default();
+    let mut labels = Vec::new();
+
+    if let Some((prefix, _)) = header.split_once(':') {
+        let breaking = prefix.ends_with('!');
+        let prefix = prefix.trim_end_matches('!');
+        let kind = prefix.split('(').next().unwrap_or_default();
+        if !kind.is_empty() && kind.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            labels.push(prefix.to_string());
+            if kind != prefix {
+                labels.push(kind.to_string());
+            }
+            if breaking {
+                labels.push("BREAKING CHANGE".to_string());
+            }
+        }
+    }
+
+    let has_footer = message
+        .lines()
+        .any(|line| line.starts_with("BREAKING CHANGE:") || line.starts_with("BREAKING-CHANGE:"));
+    if has_footer && !labels.iter().any(|l| l == "BREAKING CHANGE") {
+        labels.push("BREAKING CHANGE".to_string());
+    }
+    labels
+}
+
+/// Decide what to do with an update from the commit messages in its range.
+/// The strictest action wins; commits no rule matches are deployed normally.
+/// Returns the action and the label that caused it.
+pub fn evaluate(rules: &[CommitRule], messages: &[String]) -> (CommitAction, Option<String>) {
+    let mut decision = (CommitAction::Deploy, None);
+
+    for message in messages {
+        for label in labels(message) {
+            let rule = rules
+                .iter()
+                .find(|rule| rule.types.iter().any(|pattern| glob_match(pattern, &label)));
+            if let Some(rule) = rule {
+                if rule.action > decision.0 {
+                    decision = (rule.action, Some(label));
+                }
+            }
+        }
+    }
+    decision
+}
@@ -1,19 +1,535 @@
-use super::repo_config::RepoCfg;
+use super::allowlist::Cidr;
+use super::repo_config::{Credentials, IpFamily, RepoCfg};
 use super::errors::{Result, WatchError};
+use super::glob::glob_match;
+use super::lint;
+use super::secrets;
 use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+fn default_stats_interval_hours() -> u64 { 24 }
+fn default_metrics_prefix() -> String { "rustpdater".to_string() }
+fn default_metrics_interval() -> String { "10s".to_string() }
+fn default_control_socket() -> PathBuf { PathBuf::from("/run/rustpdater.sock") }
+fn default_shutdown_timeout() -> String { "60s".to_string() }
+fn default_webhook_path() -> String { "/webhook".to_string() }
+fn default_discover_depth() -> usize { 3 }
+
+/// Files may include files that include files, but not forever
+const MAX_INCLUDE_DEPTH: usize = 8;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
-    pub repos: Vec<RepoCfg>
+    #[serde(default)]
+    pub repos: Vec<RepoCfg>,
+    /// Directories searched for checkouts to watch with the `[defaults]` settings
+    #[serde(default)]
+    pub discover: Vec<DiscoverCfg>,
+    /// Hours between summary log lines (0 disables them)
+    #[serde(default = "default_stats_interval_hours")]
+    pub stats_interval_hours: u64,
+    /// Address family for every repo that doesn't set its own
+    #[serde(default)]
+    pub ip_family: IpFamily,
+    /// SSH connection sharing for every repo that doesn't set its own
+    #[serde(default)]
+    pub ssh_multiplex: bool,
+    /// HTTPS token source for every repo that doesn't set its own (optional)
+    #[serde(alias = "auth")]
+    pub credentials: Option<Credentials>,
+    /// Check repos sharing a remote host back-to-back from one loop
+    #[serde(default)]
+    pub group_by_host: bool,
+    /// Window the first checks are spread over at startup, e.g. "2m" (optional)
+    pub startup_ramp: Option<String>,
+    /// Most checks running at the same time; others wait, highest `priority` first (optional)
+    pub max_concurrent_checks: Option<usize>,
+    /// Log a repo's identical check error at most once per window, e.g. "15m" (optional)
+    pub failure_log_window: Option<String>,
+    /// Push counters and timings to statsd or Graphite (optional)
+    pub metrics: Option<MetricsCfg>,
+    /// HTTP listener for push webhooks that check the pushed repo right away (optional)
+    pub webhook: Option<WebhookCfg>,
+    /// JSON summary of every repo, replaced after each check (optional)
+    pub status_file: Option<PathBuf>,
+    /// Address serving `/healthz` and `/status` (optional)
+    pub status_listen: Option<SocketAddr>,
+    /// Serve `status_listen` over HTTPS with this certificate (optional)
+    pub status_tls: Option<TlsCfg>,
+    /// Unix socket `rustpdater update` talks to the running daemon over; empty disables it
+    #[serde(default = "default_control_socket")]
+    pub control_socket: PathBuf,
+    /// How long SIGTERM/SIGINT wait for running checks and hooks before exiting anyway
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: String,
+    /// Refuse to start on keys no setting reads instead of warning about them
+    #[serde(default)]
+    pub strict_config: bool,
+    /// Non-fatal problems found while loading, see `lint`
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DiscoverCfg {
+    /// Directory searched for git checkouts
+    pub root: PathBuf,
+    /// Directory levels below `root` searched; checkouts aren't searched further
+    #[serde(default = "default_discover_depth")]
+    pub max_depth: usize,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MetricsCfg {
+    #[serde(default)]
+    pub protocol: MetricsProtocol,
+    /// host:port of the statsd (UDP) or Graphite plaintext (TCP) receiver
+    pub address: String,
+    /// Prefix of every metric name
+    #[serde(default = "default_metrics_prefix")]
+    pub prefix: String,
+    /// Time between pushes, e.g. "10s"
+    #[serde(default = "default_metrics_interval")]
+    pub interval: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookCfg {
+    /// Address to listen on, e.g. "0.0.0.0:8080"
+    pub listen: SocketAddr,
+    /// Request path deliveries are posted to
+    #[serde(default = "default_webhook_path")]
+    pub path: String,
+    /// `webhook_secret` of repos that don't set their own (optional)
+    pub secret: Option<String>,
+    /// Required `X-Gitlab-Token` of GitLab deliveries (optional)
+    pub gitlab_token: Option<String>,
+    /// Required `Authorization` header of Gitea/Forgejo deliveries (optional)
+    pub gitea_token: Option<String>,
+    /// Address ranges deliveries are accepted from; empty accepts any
+    #[serde(default)]
+    pub allow_from: Vec<Cidr>,
+    /// Also accept the ranges GitHub publishes for its hooks
+    #[serde(default)]
+    pub allow_github_hooks: bool,
+    /// Serve HTTPS with this certificate (optional)
+    pub tls: Option<TlsCfg>,
+}
+
+/// PEM files of a listener serving HTTPS, reloaded when they change
+#[derive(Deserialize, Debug, Clone)]
+pub struct TlsCfg {
+    /// Certificate chain, leaf first
+    pub cert: PathBuf,
+    /// Private key (PKCS#8, PKCS#1 or SEC1)
+    pub key: PathBuf,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsProtocol {
+    #[default]
+    Statsd,
+    Graphite,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            repos: Vec::new(),
+            discover: Vec::new(),
+            stats_interval_hours: default_stats_interval_hours(),
+            ip_family: IpFamily::default(),
+            ssh_multiplex: false,
+            credentials: None,
+            group_by_host: false,
+            startup_ramp: None,
+            max_concurrent_checks: None,
+            failure_log_window: None,
+            metrics: None,
+            webhook: None,
+            status_file: None,
+            status_listen: None,
+            status_tls: None,
+            control_socket: default_control_socket(),
+            shutdown_timeout: default_shutdown_timeout(),
+            strict_config: false,
+            warnings: Vec::new(),
+        }
+    }
 }
 
 impl Config {
-    pub fn load_config(path: &str) -> Result<Config> {
-        let file_text = std::fs::read_to_string(path).map_err(|e| WatchError::Config {
-            path: path.to_string(),
-            source: e,
-        })?;
-        let config: Config = toml::from_str(&file_text)?;
+    /// Load one or more config files, later files overriding earlier ones.
+    /// Tables are merged key by key and repos with the same `path` are merged
+    /// into one entry; new repos, templates and discover roots are appended. Files named by a file's `include`
+    /// globs are read right after it. `[defaults]` is filled into every
+    /// repo, `[[templates]]` and `branches` maps are expanded into repos and
+    /// `enc:` values are decrypted.
+    /// With `strict` (or `strict_config` in the file) unknown keys are an error.
+    pub fn load_config(paths: &[String], strict: bool) -> Result<Config> {
+        let mut merged = Value::Table(Default::default());
+        for path in paths {
+            read_with_includes(&mut merged, Path::new(path), 0)?;
+        }
+        Config::from_merged(merged, strict)
+    }
+
+    /// The repo at `path` as the daemon would configure it if `repo` were added
+    /// to `config_path`: `[defaults]` and global settings such as `credentials`
+    /// filled in. A missing file counts as empty.
+    pub fn preview_repo(config_path: &Path, path: &Path, repo: toml::Table) -> Result<RepoCfg> {
+        let mut merged = Value::Table(Default::default());
+        if config_path.exists() {
+            read_with_includes(&mut merged, config_path, 0)?;
+        }
+        let mut added = toml::Table::new();
+        added.insert("repos".to_string(), Value::Array(vec![Value::Table(repo)]));
+        merge_values(&mut merged, Value::Table(added));
+
+        let config = Config::from_merged(merged, false)?;
+        config.repos.into_iter().find(|repo| repo.path == path).ok_or_else(|| WatchError::UnknownRepo(path.display().to_string()))
+    }
+
+    /// Turn the merged files into the config: templates, defaults, branches,
+    /// discovery and secrets, then the global settings repos inherit
+    fn from_merged(mut merged: Value, strict: bool) -> Result<Config> {
+        expand_templates(&mut merged);
+        let defaults = apply_defaults(&mut merged);
+        expand_branches(&mut merged);
+        discover_repos(&mut merged, defaults.as_ref())?;
+        secrets::decrypt_values(&mut merged)?;
+        let mut unknown = Vec::new();
+        let mut config: Config = serde_ignored::deserialize(merged, |path| unknown.push(path.to_string()))?;
+        if (strict || config.strict_config) && !unknown.is_empty() {
+            return Err(WatchError::UnknownKeys(lint::describe_keys(&unknown).join(", ")));
+        }
+        for repo in &mut config.repos {
+            repo.ip_family.get_or_insert(config.ip_family);
+            repo.ssh_multiplex.get_or_insert(config.ssh_multiplex);
+            if repo.credentials.is_none() {
+                repo.credentials.clone_from(&config.credentials);
+            }
+        }
+        config.warnings = lint::lint(&config, &unknown);
         Ok(config)
     }
+
+    /// The control socket, unless disabled with an empty path
+    pub fn control_socket(&self) -> Option<&Path> {
+        (!self.control_socket.as_os_str().is_empty()).then_some(self.control_socket.as_path())
+    }
+
+    /// Find a repo by its `name`, its path or the last component of it
+    pub fn find_repo(&self, name: &str) -> Option<&RepoCfg> {
+        self.repos.iter().find(|repo| repo.name.as_deref() == Some(name)).or_else(|| {
+            self.repos
+                .iter()
+                .find(|repo| repo.path.as_os_str() == name || repo.path.file_name().is_some_and(|f| f == name))
+        })
+    }
+}
+
+/// Merge a file into `merged`, then the files its `include` (a glob or a list
+/// of them, relative to the file) names in sorted order, so drop-ins such as
+/// `conf.d/*.toml` override the file including them
+fn read_with_includes(merged: &mut Value, path: &Path, depth: usize) -> Result<()> {
+    let display = path.display().to_string();
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(WatchError::Config {
+            path: display,
+            source: std::io::Error::other(format!("includes nested deeper than {MAX_INCLUDE_DEPTH} levels")),
+        });
+    }
+    let mut value = read_value(&display)?;
+    let include = value.as_table_mut().and_then(|table| table.remove("include"));
+    merge_values(merged, value);
+
+    let patterns = match include {
+        Some(Value::String(pattern)) => vec![pattern],
+        Some(Value::Array(patterns)) => patterns.into_iter().filter_map(|p| p.as_str().map(str::to_string)).collect(),
+        _ => Vec::new(),
+    };
+    let base = path.parent().unwrap_or(Path::new("."));
+    for pattern in patterns {
+        for file in expand_include(&base.join(pattern)) {
+            read_with_includes(merged, &file, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// Every file `load_config` reads for `paths`, includes included, in the order
+/// they are merged
+pub fn source_files(paths: &[String]) -> Result<Vec<PathBuf>> {
+    fn walk(files: &mut Vec<PathBuf>, path: &Path, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Ok(());
+        }
+        files.push(path.to_path_buf());
+        let patterns = match read_value(&path.display().to_string())?.get("include") {
+            Some(Value::String(pattern)) => vec![pattern.clone()],
+            Some(Value::Array(patterns)) => patterns.iter().filter_map(|p| p.as_str().map(str::to_string)).collect(),
+            _ => Vec::new(),
+        };
+        let base = path.parent().unwrap_or(Path::new("."));
+        for pattern in patterns {
+            for file in expand_include(&base.join(pattern)) {
+                walk(files, &file, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        walk(&mut files, Path::new(path), 0)?;
+    }
+    Ok(files)
+}
+
+/// Files matching a glob in its last component; a missing directory matches nothing.
+/// A plain path is returned as is, so a missing file is reported.
+fn expand_include(pattern: &Path) -> Vec<PathBuf> {
+    let name = pattern.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return vec![pattern.to_path_buf()];
+    }
+    let dir = pattern.parent().unwrap_or(Path::new("."));
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| entry.path().is_file() && glob_match(&name, &entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .collect();
+    files.sort();
+    files
+}
+
+fn read_value(path: &str) -> Result<Value> {
+    let file_text = std::fs::read_to_string(path).map_err(|e| WatchError::Config {
+        path: path.to_string(),
+        source: e,
+    })?;
+    let value: Value = toml::from_str(&file_text)?;
+    Ok(value)
+}
+
+/// Turn every `[[templates]]` entry into one repo per item of its `instances` list.
+/// `{{name}}` placeholders are replaced with the instance's values, which are
+/// also handed to the hooks as environment variables.
+fn expand_templates(config: &mut Value) {
+    let Some(table) = config.as_table_mut() else {
+        return;
+    };
+    let Some(Value::Array(templates)) = table.remove("templates") else {
+        return;
+    };
+
+    let mut expanded = Vec::new();
+    for mut template in templates {
+        let instances = match template.as_table_mut().and_then(|t| t.remove("instances")) {
+            Some(Value::Array(instances)) => instances,
+            _ => Vec::new(),
+        };
+        for instance in instances.iter().filter_map(Value::as_table) {
+            let vars: Vec<(String, String)> = instance
+                .iter()
+                .map(|(key, value)| (key.clone(), value.as_str().map_or_else(|| value.to_string(), str::to_string)))
+                .collect();
+
+            let mut repo = template.clone();
+            substitute(&mut repo, &vars);
+            if let Some(repo_table) = repo.as_table_mut() {
+                let env = repo_table
+                    .entry("env")
+                    .or_insert_with(|| Value::Table(Default::default()));
+                if let Some(env) = env.as_table_mut() {
+                    for (key, value) in &vars {
+                        env.entry(key.clone()).or_insert_with(|| Value::String(value.clone()));
+                    }
+                }
+            }
+            expanded.push(repo);
+        }
+    }
+
+    let repos = table.entry("repos").or_insert_with(|| Value::Array(Vec::new()));
+    if let Some(repos) = repos.as_array_mut() {
+        repos.extend(expanded);
+    }
+}
+
+/// Give every repo the keys of the `[defaults]` table it doesn't set itself.
+/// Tables such as `env` are merged key by key, the repo's values winning.
+/// Returns the defaults for repos added later.
+fn apply_defaults(config: &mut Value) -> Option<Value> {
+    let table = config.as_table_mut()?;
+    let defaults = table.remove("defaults")?;
+    if let Some(Value::Array(repos)) = table.get_mut("repos") {
+        for repo in repos {
+            fill_defaults(repo, &defaults);
+        }
+    }
+    Some(defaults)
+}
+
+fn fill_defaults(repo: &mut Value, defaults: &Value) {
+    let mut filled = defaults.clone();
+    merge_values(&mut filled, std::mem::replace(repo, Value::Boolean(false)));
+    *repo = filled;
+}
+
+/// Add a repo for every checkout found under a `[[discover]]` root that isn't
+/// configured already. Roots that aren't directories are left to `lint`.
+fn discover_repos(config: &mut Value, defaults: Option<&Value>) -> Result<()> {
+    let Some(discover) = config.get("discover").cloned() else {
+        return Ok(());
+    };
+    let discover = Vec::<DiscoverCfg>::deserialize(discover)?;
+    let Some(table) = config.as_table_mut() else {
+        return Ok(());
+    };
+    let Value::Array(repos) = table.entry("repos").or_insert_with(|| Value::Array(Vec::new())) else {
+        return Ok(());
+    };
+
+    let mut configured: HashSet<PathBuf> =
+        repos.iter().filter_map(|repo| repo.get("path")?.as_str()).map(PathBuf::from).collect();
+    for cfg in &discover {
+        let mut found = Vec::new();
+        find_checkouts(&cfg.root, cfg.max_depth, &mut found);
+        found.sort();
+        for path in found {
+            if !configured.insert(path.clone()) {
+                continue;
+            }
+            let mut repo = toml::map::Map::new();
+            repo.insert("path".to_string(), Value::String(path.display().to_string()));
+            let mut repo = Value::Table(repo);
+            if let Some(defaults) = defaults {
+                fill_defaults(&mut repo, defaults);
+            }
+            repos.push(repo);
+        }
+    }
+    Ok(())
+}
+
+/// Directories with a `.git` at most `depth` levels below `dir`, not descending
+/// into checkouts, hidden directories or symlinks
+fn find_checkouts(dir: &Path, depth: usize, found: &mut Vec<PathBuf>) {
+    if dir.join(".git").exists() {
+        found.push(dir.to_path_buf());
+        return;
+    }
+    if depth == 0 {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            find_checkouts(&entry.path(), depth - 1, found);
+        }
+    }
+}
+
+/// Replace every repo carrying a `branches` map with one repo per branch.
+/// Each branch gets its own worktree of the clone at the entry's `path`, so the
+/// object store is shared; keys set on a branch override the entry's own.
+fn expand_branches(config: &mut Value) {
+    let Some(Value::Array(repos)) = config.get_mut("repos") else {
+        return;
+    };
+
+    let mut expanded = Vec::new();
+    for mut repo in repos.drain(..) {
+        let branches = match repo.as_table_mut().and_then(|t| t.remove("branches")) {
+            Some(Value::Table(branches)) => branches,
+            _ => {
+                expanded.push(repo);
+                continue;
+            }
+        };
+
+        for (branch, overrides) in branches {
+            let mut branch_repo = repo.clone();
+            if let Some(table) = branch_repo.as_table_mut() {
+                if let Some(shared) = table.remove("path") {
+                    table.insert("shared_clone".to_string(), shared);
+                }
+                table.insert("branch".to_string(), Value::String(branch));
+            }
+            merge_values(&mut branch_repo, overrides);
+            expanded.push(branch_repo);
+        }
+    }
+    *repos = expanded;
+}
+
+fn substitute(value: &mut Value, vars: &[(String, String)]) {
+    match value {
+        Value::String(text) => {
+            for (key, replacement) in vars {
+                *text = text.replace(&format!("{{{{{key}}}}}"), replacement);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| substitute(item, vars)),
+        Value::Table(table) => table.iter_mut().for_each(|(_, item)| substitute(item, vars)),
+        _ => {}
+    }
+}
+
+/// Merge `overlay` into `base`, with overlay values winning
+fn merge_values(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) if key == "repos" => merge_repos(existing, value),
+                    Some(existing) if key == "templates" || key == "discover" => append_values(existing, value),
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Add the entries of a later `[[templates]]` or `[[discover]]` array to the earlier ones
+fn append_values(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Array(base), Value::Array(overlay)) => base.extend(overlay),
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Merge two `[[repos]]` arrays, matching entries by their `path`
+fn merge_repos(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Array(base), Value::Array(overlay)) => {
+            for repo in overlay {
+                let existing = base
+                    .iter_mut()
+                    .find(|candidate| repo.get("path").is_some() && candidate.get("path") == repo.get("path"));
+                match existing {
+                    Some(existing) => merge_values(existing, repo),
+                    None => base.push(repo),
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
 }
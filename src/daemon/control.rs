@@ -0,0 +1,79 @@
+use super::errors::{Result, WatchError};
+use super::watcher::Triggers;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+use tokio::net::{UnixListener, UnixStream};
+use log::{info, warn};
+
+/// Answer `update <repo>` lines on the control socket by triggering a check
+/// of that repo. Only the socket's owner (normally root) may connect.
+pub async fn serve(path: &Path, triggers: Arc<Triggers>) -> Result<()> {
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(WatchError::CommandFailed {
+            command: "control socket".to_string(),
+            stderr: format!("another rustpdater is already listening on {}", path.display()),
+        });
+    }
+    // Left behind by a daemon that didn't shut down cleanly
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    info!("Control socket on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let triggers = triggers.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &triggers).await {
+                warn!("Control request failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: UnixStream, triggers: &Triggers) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    AsyncBufReader::new(reader).read_line(&mut line).await?;
+
+    let reply = match line.trim().split_once(' ') {
+        Some(("update", name)) => match find_repo(&triggers.paths(), name.trim()) {
+            Some(path) if triggers.trigger(&path, "manual") => {
+                info!("Check of {} requested over the control socket", path.display());
+                format!("ok checking {}", path.display())
+            }
+            _ => format!("error no watched repo named '{}'", name.trim()),
+        },
+        _ => format!("error unknown command '{}'", line.trim()),
+    };
+    writer.write_all(format!("{reply}\n").as_bytes()).await?;
+    Ok(())
+}
+
+/// Match a repo by its path or the last component of it, like `Config::find_repo`
+fn find_repo(paths: &[PathBuf], name: &str) -> Option<PathBuf> {
+    paths
+        .iter()
+        .find(|path| path.as_os_str() == name || path.file_name().is_some_and(|f| f == name))
+        .cloned()
+}
+
+/// Send one command to the running daemon and return its reply
+pub fn send(path: &Path, command: &str) -> Result<String> {
+    let mut stream = std::os::unix::net::UnixStream::connect(path).map_err(|e| WatchError::CommandFailed {
+        command: format!("connect to {}", path.display()),
+        stderr: format!("{e} (is the daemon running, and are you allowed to use its socket?)"),
+    })?;
+    stream.write_all(format!("{command}\n").as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    match reply.trim().split_once(' ') {
+        Some(("ok", message)) => Ok(message.to_string()),
+        Some(("error", message)) => Err(WatchError::CommandFailed { command: command.to_string(), stderr: message.to_string() }),
+        _ => Err(WatchError::CommandFailed { command: command.to_string(), stderr: format!("unexpected reply '{}'", reply.trim()) }),
+    }
+}
@@ -0,0 +1,119 @@
+use super::errors::{Result, WatchError};
+use super::repo_config::{CredentialCli, Credentials, RepoCfg};
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use log::{info, warn};
+
+/// Environment variable the inline credential helper reads the token from,
+/// so it never shows up in the process list
+const TOKEN_ENV: &str = "RUSTPDATER_GIT_TOKEN";
+const USERNAME_ENV: &str = "RUSTPDATER_GIT_USERNAME";
+/// How long a token fetched from a secret store is reused before asking again
+const CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// Tokens by secret store entry, so gpg-agent isn't asked on every poll
+static CACHE: OnceLock<Mutex<HashMap<String, (String, Instant)>>> = OnceLock::new();
+
+/// Point git at the repo's configured credentials for HTTPS remotes: an inline
+/// credential helper answering with the token passed in the environment
+pub fn apply(command: &mut Command, repo: &RepoCfg, url: &str) {
+    let Some(credentials) = &repo.credentials else {
+        return;
+    };
+    // A global `credentials` also reaches repos with SSH or local remotes
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return;
+    }
+
+    match token(credentials, url) {
+        Ok(token) => {
+            command
+                .args(["-c", "credential.helper="])
+                .args(["-c", &format!(
+                    "credential.helper=!f() {{ test \"$1\" = get && echo username=${USERNAME_ENV} && echo password=${TOKEN_ENV}; }}; f"
+                )])
+                .env(USERNAME_ENV, credentials.username())
+                .env(TOKEN_ENV, token);
+        }
+        Err(e) => warn!("No credentials for {}: {}", repo.name(), e),
+    }
+}
+
+fn token(credentials: &Credentials, url: &str) -> Result<String> {
+    match credentials {
+        Credentials::Cli(CredentialCli::Gh) => cached(&expand_entry("gh:{host}", url), gh_auth_token),
+        Credentials::Pass { pass, .. } => cached(&expand_entry(pass, url), pass_show),
+        Credentials::Env { token_env, .. } => env_token(token_env),
+    }
+}
+
+/// Token from the daemon's environment, e.g. `EnvironmentFile=` of the unit
+fn env_token(name: &str) -> Result<String> {
+    match std::env::var(name) {
+        Ok(token) if !token.trim().is_empty() => Ok(token.trim().to_string()),
+        _ => Err(WatchError::CommandFailed {
+            command: format!("read ${name}"),
+            stderr: "environment variable is not set or empty".to_string(),
+        }),
+    }
+}
+
+fn cached(key: &str, fetch: impl Fn(&str) -> Result<String>) -> Result<String> {
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some((token, fetched)) = cache.lock().unwrap().get(key) {
+        if fetched.elapsed() < CACHE_TTL {
+            return Ok(token.clone());
+        }
+    }
+
+    let token = fetch(key)?;
+    cache.lock().unwrap().insert(key.to_string(), (token.clone(), Instant::now()));
+    Ok(token)
+}
+
+/// First line of `pass show <entry>`, the password by pass convention
+fn pass_show(entry: &str) -> Result<String> {
+    info!("Reading credentials from pass entry {}", entry);
+    let output = Command::new("pass")
+        .args(["show", entry])
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(WatchError::CommandFailed {
+            command: format!("pass show {entry}"),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    let stdout = String::from_utf8(output.stdout)?;
+    Ok(stdout.lines().next().unwrap_or_default().to_string())
+}
+
+/// Token the GitHub CLI stored for the host of a `gh:<host>` key
+fn gh_auth_token(key: &str) -> Result<String> {
+    let host = key.trim_start_matches("gh:");
+    info!("Reading credentials for {} from the GitHub CLI", host);
+    let output = Command::new("gh")
+        .args(["auth", "token", "--hostname", host])
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(WatchError::CommandFailed {
+            command: format!("gh auth token --hostname {host}"),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Fill `{host}` and `{repo}` (the remote path without `.git`) from the URL
+fn expand_entry(template: &str, url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    let repo = path.trim_matches('/').trim_end_matches(".git");
+    template.replace("{host}", host).replace("{repo}", repo)
+}
@@ -0,0 +1,101 @@
+use super::duration::parse_duration;
+use super::errors::{Result, WatchError};
+use super::git_ops::{git_output, remote_config_args, remote_git_output};
+use super::github::hostname;
+use super::repo_config::{DeployLockCfg, RepoCfg};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use log::{info, warn};
+
+/// Lease used when `lease` doesn't parse
+const DEFAULT_LEASE: Duration = Duration::from_secs(15 * 60);
+
+/// Push output meaning the lease didn't hold because the slot ref moved meanwhile
+const LOST_RACE: &[&str] = &["(stale info)", "[rejected]", "incorrect old value provided"];
+
+/// A deploy slot held on the remote until `release`
+pub struct DeployLock {
+    ref_name: String,
+    sha: String,
+}
+
+/// Take one of the `slots` lock refs on the remote, so hosts watching the same
+/// repo update in batches of at most that size. Each slot is a ref holding a
+/// small commit; pushing it with `--force-with-lease` makes taking it atomic.
+/// Slots older than the lease are taken over from hosts that died mid-deploy.
+/// Returns None when every slot is busy.
+pub fn acquire(repo: &RepoCfg, cfg: &DeployLockCfg, url: &str) -> Result<Option<DeployLock>> {
+    let name = cfg.name.as_deref().unwrap_or_else(|| repo.branch());
+    let prefix = format!("refs/rustpdater/locks/{name}");
+    let lease = parse_duration(&cfg.lease).unwrap_or(DEFAULT_LEASE);
+
+    let mut args = remote_config_args(url);
+    let pattern = format!("{prefix}/*");
+    args.extend(["ls-remote", url, &pattern]);
+    let held: BTreeMap<String, String> = remote_git_output(repo, &args)?
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(sha, name)| (name.to_string(), sha.to_string()))
+        .collect();
+
+    let commit = lock_commit(repo)?;
+    for slot in 0..cfg.slots.max(1) {
+        let ref_name = format!("{prefix}/{slot}");
+        let expected = match held.get(&ref_name) {
+            None => String::new(),
+            Some(sha) if is_stale(repo, url, &ref_name, sha, lease)? => {
+                warn!("Taking over deploy slot {} for {}: its lease expired", ref_name, repo.name());
+                sha.clone()
+            }
+            Some(_) => continue,
+        };
+
+        // An empty expected value means the ref must not exist yet
+        let lease_arg = format!("--force-with-lease={ref_name}:{expected}");
+        let refspec = format!("{commit}:{ref_name}");
+        let mut args = remote_config_args(url);
+        args.extend(["push", &lease_arg, url, &refspec]);
+        match remote_git_output(repo, &args) {
+            Ok(_) => {
+                info!("Took deploy slot {} for {}", ref_name, repo.name());
+                return Ok(Some(DeployLock { ref_name, sha: commit }));
+            }
+            // Another host got there first; auth, permission or network errors are the check's
+            Err(WatchError::GitCommandFailed { stderr, .. }) if LOST_RACE.iter().any(|marker| stderr.contains(marker)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// Give the slot back; if that fails the lease frees it eventually
+pub fn release(repo: &RepoCfg, url: &str, lock: DeployLock) {
+    let lease_arg = format!("--force-with-lease={}:{}", lock.ref_name, lock.sha);
+    let refspec = format!(":{}", lock.ref_name);
+    let mut args = remote_config_args(url);
+    args.extend(["push", &lease_arg, url, &refspec]);
+    match remote_git_output(repo, &args) {
+        Ok(_) => info!("Released deploy slot {} for {}", lock.ref_name, repo.name()),
+        Err(e) => warn!("Could not release deploy slot {} for {}: {}", lock.ref_name, repo.name(), e),
+    }
+}
+
+/// Empty-tree commit naming this host; its commit time dates the lease
+fn lock_commit(repo: &RepoCfg) -> Result<String> {
+    let tree = git_output(repo, &["hash-object", "-w", "-t", "tree", "/dev/null"])?;
+    let message = format!("deploy lock held by {} (pid {})", hostname(), std::process::id());
+    git_output(repo, &[
+        "-c", "user.name=rustpdater",
+        "-c", "user.email=rustpdater@localhost",
+        "commit-tree", &tree, "-m", &message,
+    ])
+}
+
+fn is_stale(repo: &RepoCfg, url: &str, ref_name: &str, sha: &str, lease: Duration) -> Result<bool> {
+    let mut args = remote_config_args(url);
+    args.extend(["fetch", "--no-tags", url, ref_name]);
+    remote_git_output(repo, &args)?;
+    let taken_at: u64 = git_output(repo, &["show", "-s", "--format=%ct", sha])?.parse().unwrap_or(0);
+    let now = jiff::Timestamp::now().as_second().max(0) as u64;
+    Ok(now.saturating_sub(taken_at) > lease.as_secs())
+}
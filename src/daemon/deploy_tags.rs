@@ -0,0 +1,36 @@
+use super::errors::Result;
+use super::git_ops::{git_output, UpdateEvent};
+use super::repo_config::{DeployTagsCfg, RepoCfg};
+use log::info;
+
+/// Put a lightweight tag on the commit just deployed and prune the oldest
+/// deploy tags beyond `keep`. Tags sharing the pattern's fixed prefix are
+/// considered deploy tags.
+pub fn tag(repo: &RepoCfg, cfg: &DeployTagsCfg, event: &UpdateEvent) -> Result<()> {
+    let short_sha = event.new_sha.get(..12).unwrap_or(&event.new_sha);
+    let name = cfg
+        .pattern
+        .replace("{timestamp}", &jiff::Timestamp::now().strftime("%Y%m%dT%H%M%SZ").to_string())
+        .replace("{sha}", short_sha)
+        .replace("{branch}", &event.branch);
+    git_output(repo, &["tag", "--force", &name, &event.new_sha])?;
+    info!("Tagged deploy of {} as {}", repo.name(), name);
+
+    let Some(keep) = cfg.keep else {
+        return Ok(());
+    };
+    let prefix = cfg.pattern.split('{').next().unwrap_or_default();
+    let pattern = format!("refs/tags/{prefix}*");
+    // Lightweight tags carry no date of their own: a leading timestamp orders
+    // them by deploy time, otherwise the commit date has to do
+    let sort = if cfg.pattern[prefix.len()..].starts_with("{timestamp}") { "--sort=-refname" } else { "--sort=-creatordate" };
+    let tags = git_output(repo, &["for-each-ref", sort, "--format=%(refname:short)", &pattern])?;
+    let stale: Vec<&str> = tags.lines().skip(keep.max(1)).collect();
+    if !stale.is_empty() {
+        let mut args = vec!["tag", "--delete"];
+        args.extend(&stale);
+        git_output(repo, &args)?;
+        info!("Pruned {} old deploy tag(s) of {}", stale.len(), repo.name());
+    }
+    Ok(())
+}
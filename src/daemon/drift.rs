@@ -0,0 +1,170 @@
+use super::errors::Result;
+use super::git_ops::{self, git_output};
+use super::hooks;
+use super::repo_config::{DriftPolicy, RepoCfg};
+use notify::{RecursiveMode, Watcher};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use log::{error, info, warn};
+
+/// Quiet period after a file event before the tree is inspected, so an
+/// editor saving several files triggers one check
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// How often an idle watch looks whether its poll loop stopped
+const STOP_CHECK: Duration = Duration::from_secs(1);
+
+/// `drift_backup` value that keeps drift in `git stash` instead of a directory
+const STASH: &str = "stash";
+
+/// Files in the working tree that differ from HEAD, as `git status` paths.
+/// Ignored files don't count, untracked ones do.
+pub fn drifted_files(repo: &RepoCfg) -> Result<Vec<String>> {
+    let status = git_output(repo, &["status", "--porcelain=v2", "-z", "--untracked-files=all"])?;
+    let mut entries = status.split('\0').filter(|entry| !entry.is_empty());
+    let mut files = Vec::new();
+    while let Some(entry) = entries.next() {
+        // v2 entries: "1 <8 fields> path", "2 <9 fields> path\0orig", "u <10 fields> path", "? path"
+        let path = match entry.as_bytes().first() {
+            Some(b'1') => entry.splitn(9, ' ').nth(8),
+            Some(b'2') => {
+                entries.next();
+                entry.splitn(10, ' ').nth(9)
+            }
+            Some(b'u') => entry.splitn(11, ' ').nth(10),
+            Some(b'?') => entry.get(2..),
+            _ => None,
+        };
+        files.extend(path.map(str::to_string));
+    }
+    Ok(files)
+}
+
+/// Put the working tree back to HEAD when it drifted, keeping the local
+/// changes per `drift_backup` first. Returns the files that had drifted.
+pub fn reconcile(repo: &RepoCfg) -> Result<Vec<String>> {
+    let files = drifted_files(repo)?;
+    if files.is_empty() {
+        return Ok(files);
+    }
+    warn!("Local drift in {}: {}", repo.name(), files.join(", "));
+
+    match repo.drift_backup.as_deref() {
+        Some(STASH) => {
+            let message = format!("rustpdater drift {}", jiff::Timestamp::now());
+            git_output(repo, &["stash", "push", "--include-untracked", "-m", &message])?;
+            info!("Stashed the drift of {} as '{}'", repo.name(), message);
+        }
+        Some(dir) => {
+            let target = Path::new(dir).join(jiff::Timestamp::now().strftime("%Y%m%dT%H%M%SZ").to_string());
+            backup_files(&repo.path, &files, &target)?;
+            info!("Copied the drift of {} to {}", repo.name(), target.display());
+        }
+        None => {}
+    }
+
+    git_output(repo, &["reset", "--hard", "HEAD"])?;
+    git_output(repo, &["clean", "-fd"])?;
+    warn!("Reverted {} drifted file(s) in {}", files.len(), repo.name());
+    Ok(files)
+}
+
+/// Copy the modified and untracked files that still exist into `target`, keeping their paths
+fn backup_files(repo_path: &Path, files: &[String], target: &Path) -> Result<()> {
+    for file in files {
+        let source = repo_path.join(file);
+        if !source.is_file() {
+            continue;
+        }
+        let destination = target.join(file);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&source, &destination)?;
+    }
+    Ok(())
+}
+
+/// Watch the working tree and apply `drift_policy` when files change outside of
+/// an update. `busy` is held by the poll loop while it checks, so our own resets
+/// are never mistaken for tampering. Blocks until the poll loop's `stop` reads
+/// true or the loop is gone.
+pub fn watch_tree(repo: &RepoCfg, busy: Arc<Mutex<()>>, stop: Option<watch::Receiver<bool>>) -> Result<()> {
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(std::io::Error::other)?;
+    watcher.watch(&repo.path, RecursiveMode::Recursive).map_err(std::io::Error::other)?;
+    info!("Watching the working tree of {} for local changes", repo.name());
+
+    // A worktree's git dir lives in the shared clone, only its `.git` file is in the tree
+    let (git_dir, dot_git) = (git_ops::git_dir(&repo.path)?, repo.path.join(".git"));
+    let mut rehooked = None;
+    let stopped = || stop.as_ref().is_some_and(|stop| *stop.borrow() || stop.has_changed().is_err());
+    loop {
+        let event = match events.recv_timeout(STOP_CHECK) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) if !stopped() => continue,
+            Err(_) => break,
+        };
+        let outside_git = |event: &notify::Result<notify::Event>| {
+            event.as_ref().is_ok_and(|e| e.paths.iter().any(|p| !p.starts_with(&git_dir) && !p.starts_with(&dot_git)))
+        };
+        if !outside_git(&event) {
+            continue;
+        }
+        while events.recv_timeout(SETTLE).is_ok() {}
+
+        let _guard = busy.lock().unwrap();
+        if stopped() {
+            break;
+        }
+        if let Err(e) = apply_policy(repo, &mut rehooked) {
+            error!("Drift check failed for {}: {}", repo.name(), e);
+        }
+        // Whatever a hook or our own revert wrote meanwhile is not tampering
+        while events.try_recv().is_ok() {}
+    }
+    info!("Stopped watching the working tree of {}", repo.name());
+    Ok(())
+}
+
+/// Act on a change in the tree. `rehooked` holds the drift left after the last
+/// `rehook` run, so files a hook writes don't run it over and over.
+fn apply_policy(repo: &RepoCfg, rehooked: &mut Option<Vec<String>>) -> Result<()> {
+    match repo.drift_policy {
+        DriftPolicy::Warn => {
+            let files = drifted_files(repo)?;
+            if !files.is_empty() {
+                warn!("Files changed outside of an update in {}: {}", repo.name(), files.join(", "));
+            }
+        }
+        DriftPolicy::Revert => {
+            reconcile(repo)?;
+        }
+        DriftPolicy::Rehook => {
+            let files = drifted_files(repo)?;
+            if files.is_empty() {
+                *rehooked = None;
+                return Ok(());
+            }
+            if rehooked.as_ref() == Some(&files) {
+                return Ok(());
+            }
+            warn!("Files changed outside of an update in {}: {}", repo.name(), files.join(", "));
+            match &repo.on_change {
+                Some(hook) => {
+                    info!("Running on_change again for {}", repo.name());
+                    let head = git_ops::current_head(&repo.path)?;
+                    let result = hooks::run_on_change(repo, hook, &git_ops::hook_vars(repo, repo.branch(), &head, &head));
+                    *rehooked = Some(drifted_files(repo)?);
+                    result?;
+                }
+                None => warn!("drift_policy = \"rehook\" but {} has no on_change", repo.name()),
+            }
+        }
+    }
+    Ok(())
+}
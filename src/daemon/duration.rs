@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+/// Parse a Go-style duration string such as "10s", "1m30s", "500ms" or "2h".
+/// A bare number is read as seconds.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if let Ok(secs) = text.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let value: f64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let unit = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let seconds = match &rest[..unit] {
+            "ms" => value / 1000.0,
+            "s" => value,
+            "m" => value * 60.0,
+            "h" => value * 3600.0,
+            "d" => value * 86400.0,
+            _ => return None,
+        };
+        rest = &rest[unit..];
+        // Values too large for a Duration are as invalid as a bad unit
+        total = total.checked_add(Duration::try_from_secs_f64(seconds).ok()?)?;
+    }
+
+    Some(total)
+}
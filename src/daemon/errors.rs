@@ -8,12 +8,40 @@ pub type Result<T> = std::result::Result<T, WatchError>;
 pub enum WatchError {
     #[error("git command failed: {command} - {stderr}")]
     GitCommandFailed { command: String, stderr: String },
+    #[error("branch '{branch}' no longer exists on the remote of {path} (deleted or renamed upstream)")]
+    BranchMissing { branch: String, path: String },
+    #[error("branch '{branch}' of {path} was rewritten upstream, not fast-forwarding (strategy = \"ff-only\")")]
+    NotFastForward { branch: String, path: String },
+    #[error("timed out after {seconds}s: {command}")]
+    Timeout { command: String, seconds: u64 },
+    #[error("hook failed: {command} - exited with {status}")]
+    HookFailed { command: String, status: String },
+    #[error("hook timed out: {command} - killed after {timeout}")]
+    HookTimeout { command: String, timeout: String },
+    #[error("command failed: {command} - {stderr}")]
+    CommandFailed { command: String, stderr: String },
+    #[error("TLS error: {0}")]
+    Tls(String),
+    #[error("could not decrypt config value: {0}")]
+    Decrypt(String),
+    #[error("no update is held for approval in {0}")]
+    NothingHeld(String),
+    #[error("{0} is not a git checkout; set `url` to clone into a missing or empty path")]
+    NotACheckout(String),
+    #[error("no repo named '{0}' in the config")]
+    UnknownRepo(String),
+    #[error("{0} has no [[repos]] entry of its own (it comes from templates, branches or discover); set `ssh_key` where it is defined")]
+    NoRepoTable(String),
+    #[error("config error: unknown keys (strict config): {0}")]
+    UnknownKeys(String),
     #[error("config error: could not load config file '{path}' - {source}")]
     Config { path: String, source: std::io::Error },
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
     #[error("toml error: {0}")]
     Toml(#[from] toml::de::Error),
+    #[error("toml error: {0}")]
+    TomlEdit(#[from] toml_edit::TomlError),
     #[error("task join error: {0}")]
     Join(#[from] JoinError),
     #[error("utf-8 error: {0}")]
@@ -1,11 +1,51 @@
 use super::errors::Result;
-use super::repo_config::RepoCfg;
+use super::commit_rules;
+use super::repo_config::{CommitAction, FetchTags, IpFamily, Mode, PushBackCfg, RefSort, RepoCfg, RetryCfg, Strategy};
+use super::duration::parse_duration;
+use super::glob::glob_match;
+use super::marker;
+use super::terraform;
+use super::credentials;
+use super::drift;
+use super::github;
+use super::deploy_lock;
+use super::deploy_tags;
+use super::hooks;
 use std::process::Command;
-use std::path::Path;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::sync::mpsc;
+use std::thread;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::env;
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
 use log::{info, warn};
 
+/// Commit waiting for approval, written inside the git dir by the daemon
+const HELD_FILE: &str = "rustpdater-held";
+/// Commit whose `on_change` failed and was rolled back, not deployed again
+const ROLLED_BACK_FILE: &str = "rustpdater-rolled-back";
+/// Commit approved by `rustpdater approve`
+const APPROVED_FILE: &str = "rustpdater-approved";
+
+/// How long an idle shared SSH connection stays open
+const SSH_CONTROL_PERSIST: &str = "10m";
+
+/// Ref pointing at the commit deployed before the latest update
+const PREVIOUS_REF: &str = "rustpdater/previous";
+/// Prefix of the per-update refs kept with `backup_refs`
+const BACKUP_REF_PREFIX: &str = "rustpdater/backup/";
+
+/// Set for ssh when this binary stands in as SSH_ASKPASS for `ssh_key_passphrase_file`
+const PASSPHRASE_FILE_ENV: &str = "RUSTPDATER_SSH_PASSPHRASE_FILE";
+
+/// Trailer marking commits pushed by `push_back`, so they never trigger hooks again
+const GENERATED_TRAILER: &str = "Rustpdater-Generated: true";
+
 /// Normalize git remote URL to fix malformed URLs but preserve SSH URLs
 fn normalize_git_url(url: &str) -> String {
     // Handle malformed URLs with duplicate paths like "git@github.com:/github.com/user/repo.git"
@@ -20,14 +60,273 @@ fn normalize_git_url(url: &str) -> String {
     url.to_string()
 }
 
+/// Point an SSH remote at the repo's `host_alias`, keeping user, port and path.
+/// Handles both `ssh://[user@]host[:port]/path` and scp-like `[user@]host:path`.
+fn apply_host_alias(repo: &RepoCfg, url: &str) -> String {
+    let Some(alias) = repo.ssh_options.as_ref().and_then(|o| o.host_alias.as_deref()) else {
+        return url.to_string();
+    };
+
+    let (scheme, rest) = match url.split_once("://") {
+        Some(("ssh", rest)) => ("ssh://", rest),
+        Some(_) => return url.to_string(),
+        None if url.contains(':') && !url.starts_with('/') => ("", url),
+        None => return url.to_string(),
+    };
+    let host_end = if scheme.is_empty() { rest.find(':') } else { rest.find(['/', ':']) };
+    let Some(host_end) = host_end else {
+        return url.to_string();
+    };
+    let host_start = rest[..host_end].rfind('@').map_or(0, |at| at + 1);
+    format!("{scheme}{}{alias}{}", &rest[..host_start], &rest[host_end..])
+}
+
+/// Host part of the repo's remote, used to group repos talking to the same server.
+/// Local paths and unparsable URLs have no host.
+pub fn remote_host(repo: &RepoCfg) -> Option<String> {
+    let url = match &repo.url {
+        Some(url) => url.clone(),
+        None => get_remote_url(&repo.path).ok()?,
+    };
+    let url = apply_host_alias(repo, &normalize_git_url(&url));
+
+    let authority = match url.split_once("://") {
+        Some(("file", _)) => return None,
+        Some((_, rest)) => rest.split('/').next()?,
+        None if url.contains(':') && !url.starts_with('/') => url.split(':').next()?,
+        None => return None,
+    };
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    (!host.is_empty()).then(|| host.to_lowercase())
+}
+
+/// Path of the repo's remote on its server, e.g. "owner/name", lowercased and
+/// without ".git". Used to match webhook payloads to repos.
+pub fn remote_repo_name(repo: &RepoCfg) -> Option<String> {
+    let url = match &repo.url {
+        Some(url) => url.clone(),
+        None => get_remote_url(&repo.path).ok()?,
+    };
+    let url = normalize_git_url(&url);
+
+    let path = match url.split_once("://") {
+        Some(("file", _)) => return None,
+        Some((_, rest)) => rest.split_once('/')?.1,
+        None if url.contains(':') && !url.starts_with('/') => url.split_once(':')?.1,
+        None => return None,
+    };
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    (!path.is_empty()).then(|| path.to_lowercase())
+}
+
+/// Extra `git -c` settings needed to talk to a remote.
+/// CodeCommit HTTPS remotes get SigV4 credentials from the AWS CLI, which picks up
+/// the instance role, so no static credentials have to be stored on the host.
+/// `codecommit://` remotes need nothing here, git runs `git-remote-codecommit` for them.
+pub fn remote_config_args(url: &str) -> Vec<&'static str> {
+    let is_codecommit_https = url.starts_with("https://git-codecommit.") && url.contains(".amazonaws.com/");
+    if !is_codecommit_https {
+        return Vec::new();
+    }
+
+    vec![
+        "-c", "credential.helper=",
+        "-c", "credential.helper=!aws codecommit credential-helper $@",
+        "-c", "credential.UseHttpPath=true",
+    ]
+}
+
+/// When ssh started this binary as its askpass program, print the key's
+/// passphrase for it and return true; the caller then exits
+pub fn answer_askpass() -> bool {
+    let Some(file) = env::var_os(PASSPHRASE_FILE_ENV) else {
+        return false;
+    };
+    match fs::read_to_string(&file) {
+        Ok(passphrase) => println!("{}", passphrase.trim_end_matches(['\r', '\n'])),
+        Err(e) => eprintln!("could not read {}: {}", Path::new(&file).display(), e),
+    }
+    true
+}
+
+/// SSH command git should use for this repo, if it needs anything beyond plain `ssh`
+fn ssh_command(repo: &RepoCfg) -> Option<String> {
+    let mut options = Vec::new();
+    if let Some(ssh_options) = &repo.ssh_options {
+        if let Some(port) = ssh_options.port {
+            options.push(format!("-p {port}"));
+        }
+        if let Some(user) = &ssh_options.user {
+            options.push(format!("-l '{user}'"));
+        }
+    }
+    if let Some(key) = &repo.ssh_key {
+        options.push(format!("-i '{}' -o IdentitiesOnly=yes", key.display()));
+    }
+    if let Some(flag) = ip_family_flag(repo) {
+        options.push(flag.to_string());
+    }
+    if repo.ssh_multiplex == Some(true) {
+        match control_dir() {
+            Ok(dir) => options.push(format!(
+                "-o ControlMaster=auto -o ControlPath='{}/%C' -o ControlPersist={SSH_CONTROL_PERSIST}",
+                dir.display()
+            )),
+            Err(e) => warn!("Not sharing SSH connections for {}: {}", repo.name(), e),
+        }
+    }
+    if options.is_empty() {
+        return None;
+    }
+    Some(format!("{} {}", base_ssh_command(repo), options.join(" ")))
+}
+
+/// The ssh command plain git would use here: `GIT_SSH_COMMAND`, then
+/// `core.sshCommand` from the repo or global gitconfig, then `ssh`.
+/// Our options are appended to it so wrappers and jump hosts keep working.
+fn base_ssh_command(repo: &RepoCfg) -> String {
+    if let Ok(command) = env::var("GIT_SSH_COMMAND") {
+        if !command.trim().is_empty() {
+            return command;
+        }
+    }
+
+    let mut git = Command::new("git");
+    git.args(["config", "--get", "core.sshCommand"]);
+    if repo.path.is_dir() {
+        git.current_dir(&repo.path);
+    }
+    match git.output() {
+        Ok(output) if output.status.success() => {
+            let command = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if command.is_empty() { "ssh".to_string() } else { command }
+        }
+        _ => "ssh".to_string(),
+    }
+}
+
+/// Private directory for the SSH control sockets, one per user/host/port
+fn control_dir() -> Result<PathBuf> {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let uid = unsafe { libc::geteuid() };
+    let dir = env::temp_dir().join(format!("rustpdater-ssh-{uid}"));
+    fs::create_dir_all(&dir)?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+    Ok(dir)
+}
+
+/// `-4`/`-6` when the repo is pinned to one address family
+fn ip_family_flag(repo: &RepoCfg) -> Option<&'static str> {
+    match repo.ip_family.unwrap_or_default() {
+        IpFamily::Auto => None,
+        IpFamily::V4 => Some("-4"),
+        IpFamily::V6 => Some("-6"),
+    }
+}
+
+/// Insert the address family flag after the subcommand of network operations.
+/// ls-remote has no such flag, it only gets the SSH side via `ssh_command`.
+fn with_ip_family<'a>(repo: &RepoCfg, args: &[&'a str]) -> Vec<&'a str> {
+    let mut args = args.to_vec();
+    if let Some(flag) = ip_family_flag(repo) {
+        let subcommand = args.iter().position(|arg| matches!(*arg, "fetch" | "clone" | "push"));
+        if let Some(index) = subcommand {
+            args.insert(index + 1, flag);
+        }
+    }
+    args
+}
+
+/// Build a `git` invocation carrying the repo's transport settings.
+/// Prompts go to the askpass program when there is one, otherwise git is told
+/// to fail rather than wait on a terminal nobody is watching.
+fn git_command(repo: &RepoCfg) -> Command {
+    let mut command = Command::new("git");
+    if let Some(ssh) = ssh_command(repo) {
+        command.env("GIT_SSH_COMMAND", ssh);
+    }
+
+    if let Some(askpass) = &repo.askpass {
+        command
+            .env("GIT_ASKPASS", askpass)
+            .env("SSH_ASKPASS", askpass)
+            .env("SSH_ASKPASS_REQUIRE", "force");
+    } else if let Some(passphrase_file) = &repo.ssh_key_passphrase_file {
+        // ssh runs this binary as its askpass program, which then prints the file
+        match env::current_exe() {
+            Ok(exe) => {
+                command
+                    .env("SSH_ASKPASS", exe)
+                    .env("SSH_ASKPASS_REQUIRE", "force")
+                    .env(PASSPHRASE_FILE_ENV, passphrase_file);
+            }
+            Err(e) => warn!("Can't pass the key passphrase of {} to ssh: {}", repo.name(), e),
+        }
+    } else if env::var_os("SSH_ASKPASS").is_some() && env::var_os("SSH_ASKPASS_REQUIRE").is_none() {
+        // ssh ignores SSH_ASKPASS while it has a terminal unless asked to prefer it
+        command.env("SSH_ASKPASS_REQUIRE", "prefer");
+    }
+    if repo.askpass.is_none() && env::var_os("GIT_ASKPASS").is_none() && env::var_os("SSH_ASKPASS").is_none() {
+        command.env("GIT_TERMINAL_PROMPT", "0");
+    }
+
+    if repo.credentials.is_some() {
+        let url = match &repo.url {
+            Some(url) => url.clone(),
+            None => get_remote_url(&repo.path).unwrap_or_default(),
+        };
+        credentials::apply(&mut command, repo, &url);
+    }
+    command
+}
+
 /// Execute a git command and return the result
-fn execute_git_command(repo_path: &Path, args: &[&str]) -> Result<()> {
+fn execute_git_command(repo: &RepoCfg, args: &[&str]) -> Result<()> {
+    git_output(repo, args)?;
+    Ok(())
+}
+
+/// Run a clone, showing git's own progress when a person is watching the
+/// terminal. Either way it is bound by `fetch_timeout_secs`.
+fn execute_git_with_progress(repo: &RepoCfg, args: &[&str]) -> Result<()> {
+    if !std::io::stderr().is_terminal() {
+        remote_git_output(repo, args)?;
+        return Ok(());
+    }
+
+    let mut args = with_ip_family(repo, args);
+    if let Some(position) = args.iter().position(|arg| *arg == "clone") {
+        args.insert(position + 1, "--progress");
+    }
     let command_str = format!("git {}", args.join(" "));
-    info!("Executing command: {} (in directory: {})", command_str, repo_path.display());
+    info!("Executing command: {} (in directory: {})", command_str, repo.path.display());
 
-    let output = Command::new("git")
-        .args(args)
-        .current_dir(repo_path)
+    let mut command = git_command(repo);
+    command.args(&args).current_dir(&repo.path);
+    let status = match repo.fetch_timeout_secs {
+        Some(seconds) => wait_within(&mut command, command_str.clone(), seconds)?.status,
+        None => command.status()?,
+    };
+    if !status.success() {
+        return Err(super::errors::WatchError::GitCommandFailed {
+            command: command_str,
+            stderr: format!("exited with {status}, see git's output above"),
+        });
+    }
+    Ok(())
+}
+
+/// Execute a git command and return its trimmed stdout
+pub fn git_output(repo: &RepoCfg, args: &[&str]) -> Result<String> {
+    let args = with_ip_family(repo, args);
+    let command_str = format!("git {}", args.join(" "));
+    info!("Executing command: {} (in directory: {})", command_str, repo.path.display());
+
+    let output = git_command(repo)
+        .args(&args)
+        .current_dir(&repo.path)
         .output()?;
 
     if !output.status.success() {
@@ -38,11 +337,191 @@ fn execute_git_command(repo_path: &Path, args: &[&str]) -> Result<()> {
         });
     }
 
-    Ok(())
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Run a git command that talks to the remote (fetch, ls-remote, clone, push)
+/// under the repo's `fetch_timeout_secs` and return its trimmed stdout
+pub fn remote_git_output(repo: &RepoCfg, args: &[&str]) -> Result<String> {
+    let Some(seconds) = repo.fetch_timeout_secs else {
+        return git_output(repo, args);
+    };
+    let args = with_ip_family(repo, args);
+    let command_str = format!("git {}", args.join(" "));
+    info!("Executing command: {} (in directory: {}, timeout {}s)", command_str, repo.path.display(), seconds);
+
+    let mut command = git_command(repo);
+    command
+        .args(&args)
+        .current_dir(&repo.path)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    let output = wait_within(&mut command, command_str.clone(), seconds)?;
+    if !output.status.success() {
+        return Err(super::errors::WatchError::GitCommandFailed {
+            command: command_str,
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Run `command` for at most `seconds`. git gets its own process group, so a
+/// timeout also takes down ssh or the remote helper it is stuck in.
+fn wait_within(command: &mut Command, command_str: String, seconds: u64) -> Result<std::process::Output> {
+    let child = command.process_group(0).spawn()?;
+    let pid = child.id();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(child.wait_with_output());
+    });
+
+    match receiver.recv_timeout(Duration::from_secs(seconds)) {
+        Ok(output) => Ok(output?),
+        Err(_) => {
+            // SAFETY: plain syscall on the group git leads
+            unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+            }
+            Err(super::errors::WatchError::Timeout { command: command_str, seconds })
+        }
+    }
+}
+
+/// `--filter=<spec>` for partial clones
+fn filter_arg(repo: &RepoCfg) -> Option<String> {
+    repo.filter.as_ref().map(|filter| format!("--filter={filter}"))
+}
+
+/// Where to fetch from: partial clones must fetch through `origin`, the
+/// promisor remote git fetches missing objects from on demand
+fn fetch_source<'a>(repo: &RepoCfg, url: &'a str) -> &'a str {
+    if repo.filter.is_some() { "origin" } else { url }
+}
+
+/// Fetch flags for the repo's `fetch_tags`/`prune_tags`
+fn tag_args(repo: &RepoCfg) -> Vec<&'static str> {
+    let mut args = match repo.fetch_tags {
+        FetchTags::None => vec!["--no-tags"],
+        FetchTags::Follow => Vec::new(),
+        FetchTags::All => vec!["--tags"],
+    };
+    if repo.prune_tags {
+        args.extend(["--prune", "--prune-tags"]);
+    }
+    args
+}
+
+/// Ask the remote which branch its HEAD points to
+pub fn detect_default_branch(repo: &RepoCfg) -> Result<String> {
+    let url = match &repo.url {
+        Some(url) => url.clone(),
+        None => get_remote_url(&repo.path)?,
+    };
+    let url = apply_host_alias(repo, &normalize_git_url(&url));
+
+    let mut args = remote_config_args(&url);
+    args.extend(["ls-remote", "--symref", &url, "HEAD"]);
+    let listing = remote_git_output(repo, &args)?;
+    listing
+        .lines()
+        .find_map(|line| line.strip_prefix("ref: refs/heads/")?.strip_suffix("\tHEAD"))
+        .map(str::to_string)
+        .ok_or_else(|| super::errors::WatchError::GitCommandFailed {
+            command: format!("git ls-remote --symref {} HEAD", url),
+            stderr: "the remote did not report a default branch".to_string(),
+        })
+}
+
+/// Whether the remote, reached with the repo's credentials, has `branch`
+pub fn remote_has_branch(repo: &RepoCfg, branch: &str) -> Result<bool> {
+    Ok(!remote_refs(repo, &format!("refs/heads/{branch}"))?.is_empty())
+}
+
+/// Names of the remote's refs matching `pattern`, as `git ls-remote` matches them,
+/// reached with the repo's credentials
+pub fn remote_refs(repo: &RepoCfg, pattern: &str) -> Result<Vec<String>> {
+    let url = match &repo.url {
+        Some(url) => url.clone(),
+        None => get_remote_url(&repo.path)?,
+    };
+    let url = apply_host_alias(repo, &normalize_git_url(&url));
+
+    let mut args = remote_config_args(&url);
+    args.extend(["ls-remote", &url, pattern]);
+    let listing = remote_git_output(repo, &args)?;
+    Ok(listing.lines().filter_map(|line| Some(line.split_once('\t')?.1.to_string())).collect())
+}
+
+/// Pick the ref to track: the configured branch, or the newest remote ref matching `ref_glob`
+fn resolve_ref(repo: &RepoCfg, url: &str) -> Result<String> {
+    let Some(glob) = &repo.ref_glob else {
+        return Ok(repo.branch().to_string());
+    };
+
+    let mut args = remote_config_args(url);
+    args.extend(["ls-remote", url, glob]);
+    let listing = remote_git_output(repo, &args)?;
+    let refs: Vec<(&str, &str)> = listing.lines().filter_map(|line| line.split_once('\t')).collect();
+    if refs.is_empty() {
+        return Err(super::errors::WatchError::GitCommandFailed {
+            command: format!("git ls-remote {} {}", url, glob),
+            stderr: "no remote refs match the ref_glob".to_string(),
+        });
+    }
+
+    let newest = match repo.ref_sort {
+        RefSort::Version => refs.iter().max_by(|a, b| compare_versions(a.1, b.1)).map(|r| r.1.to_string()),
+        RefSort::Date => {
+            // Commit dates are only known locally, so bring every candidate down first
+            let mut args = remote_config_args(url);
+            args.extend(["fetch", url]);
+            args.extend(refs.iter().map(|r| r.1));
+            remote_git_output(repo, &args)?;
+
+            let mut dated = Vec::new();
+            for (sha, name) in &refs {
+                let date: i64 = git_output(repo, &["show", "-s", "--format=%ct", sha])?.parse().unwrap_or(0);
+                dated.push((date, *name));
+            }
+            dated.into_iter().max().map(|(_, name)| name.to_string())
+        }
+    };
+
+    let newest = newest.unwrap_or_default();
+    info!("Tracking {} (newest ref matching '{}')", newest, glob);
+    Ok(newest)
+}
+
+/// Compare ref names so that "release/1.10" sorts after "release/1.9"
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn chunks(text: &str) -> Vec<(bool, &str)> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let bytes = text.as_bytes();
+        for i in 1..=bytes.len() {
+            if i == bytes.len() || bytes[i].is_ascii_digit() != bytes[start].is_ascii_digit() {
+                chunks.push((bytes[start].is_ascii_digit(), &text[start..i]));
+                start = i;
+            }
+        }
+        chunks
+    }
+
+    for (left, right) in chunks(a).into_iter().zip(chunks(b)) {
+        let order = match (left, right) {
+            ((true, l), (true, r)) => l.parse::<u128>().unwrap_or(0).cmp(&r.parse::<u128>().unwrap_or(0)),
+            ((_, l), (_, r)) => l.cmp(r),
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    a.len().cmp(&b.len())
 }
 
 /// Get the remote URL for a repository
-fn get_remote_url(repo_path: &Path) -> Result<String> {
+pub fn get_remote_url(repo_path: &Path) -> Result<String> {
     let output = Command::new("git")
         .args(["remote", "get-url", "origin"])
         .current_dir(repo_path)
@@ -63,7 +542,7 @@ fn get_remote_url(repo_path: &Path) -> Result<String> {
 }
 
 /// Get the current HEAD commit hash
-fn get_current_head(repo_path: &Path) -> Result<String> {
+pub fn current_head(repo_path: &Path) -> Result<String> {
     let output = Command::new("git")
         .args(["rev-parse", "HEAD"])
         .current_dir(repo_path)
@@ -83,80 +562,811 @@ fn get_current_head(repo_path: &Path) -> Result<String> {
     Ok(head)
 }
 
-/// Get the FETCH_HEAD commit hash
-fn get_fetch_head(repo_path: &Path) -> Result<String> {
+/// The checkout's git directory. A worktree's `.git` is a file pointing into
+/// the shared clone, so the directory is asked from git instead of assumed.
+pub fn git_dir(repo_path: &Path) -> Result<PathBuf> {
     let output = Command::new("git")
-        .args(["rev-parse", "FETCH_HEAD"])
+        .args(["rev-parse", "--git-dir"])
         .current_dir(repo_path)
         .output()?;
 
     if !output.status.success() {
         return Err(super::errors::WatchError::GitCommandFailed {
-            command: "git rev-parse FETCH_HEAD".to_string(),
+            command: "git rev-parse --git-dir".to_string(),
             stderr: String::from_utf8_lossy(&output.stderr).to_string(),
         });
     }
 
-    let fetch_head = String::from_utf8(output.stdout)?
+    // Relative to the checkout when git answers with a relative path
+    Ok(repo_path.join(String::from_utf8(output.stdout)?.trim()))
+}
+
+/// Where git keeps `name` for the checkout, e.g. `info/exclude`. For a worktree
+/// this is its own git dir or the shared clone's, as git itself would use.
+pub fn git_path(repo_path: &Path, name: &str) -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", name])
+        .current_dir(repo_path)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(super::errors::WatchError::GitCommandFailed {
+            command: format!("git rev-parse --git-path {name}"),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+
+    Ok(repo_path.join(String::from_utf8(output.stdout)?.trim()))
+}
+
+/// Local ref for HEAD, tags, other refs and whatever `ref_glob` picked
+const TRACKING_REF: &str = "refs/rustpdater/tracking";
+
+/// Local ref the fetched remote ref is stored in: `refs/remotes/origin/<branch>`
+/// for branches, `refs/rustpdater/tracking` for everything else. A `ref_glob`
+/// pick always goes to the latter, so it can be found again without knowing
+/// which ref won.
+fn tracking_ref(repo: &RepoCfg, remote_ref: &str) -> String {
+    let branch = remote_ref.strip_prefix("refs/heads/").unwrap_or(remote_ref);
+    if repo.ref_glob.is_some() || branch == "HEAD" || branch.starts_with("refs/") {
+        TRACKING_REF.to_string()
+    } else {
+        format!("refs/remotes/origin/{branch}")
+    }
+}
+
+/// Commits between the checkout and the last fetched remote commit, None for
+/// mirrors or when nothing was fetched yet
+pub fn commits_behind(repo: &RepoCfg) -> Option<u64> {
+    if repo.mode == Mode::Mirror {
+        return None;
+    }
+    let tracking = tracking_ref(repo, repo.branch());
+    git_output(repo, &["rev-list", "--count", &format!("HEAD..{tracking}")]).ok()?.trim().parse().ok()
+}
+
+/// Get the commit hash a tracking ref points to
+fn get_tracking_head(repo_path: &Path, tracking_ref: &str) -> Result<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", tracking_ref])
+        .current_dir(repo_path)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(super::errors::WatchError::GitCommandFailed {
+            command: format!("git rev-parse {tracking_ref}"),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+
+    let head = String::from_utf8(output.stdout)?
         .trim()
         .to_string();
 
-    Ok(fetch_head)
+    Ok(head)
+}
+
+/// git error messages that mean the object database or index is damaged
+const CORRUPTION_MARKERS: &[&str] = &[
+    "is corrupt",
+    "bad object",
+    "bad tree",
+    "loose object",
+    "packfile",
+    "pack has bad object",
+    "invalid sha1 pointer",
+    "unable to read sha1 file",
+    "index file smaller than expected",
+    "bad signature",
+];
+
+/// Whether an error came from a damaged repository rather than the remote or the network
+pub fn is_corruption(error: &super::errors::WatchError) -> bool {
+    match error {
+        super::errors::WatchError::GitCommandFailed { stderr, .. } => {
+            CORRUPTION_MARKERS.iter().any(|marker| stderr.contains(marker))
+        }
+        _ => false,
+    }
+}
+
+/// Move a corrupted checkout aside and clone it again from its remote.
+/// The broken copy is kept next to it as `<path>.corrupt-<timestamp>` for inspection.
+pub fn reclone_corrupt(repo: &RepoCfg) -> Result<PathBuf> {
+    let url = match &repo.url {
+        Some(url) => url.clone(),
+        None => get_remote_url(&repo.path)?,
+    };
+
+    let stamp = jiff::Timestamp::now().strftime("%Y%m%dT%H%M%SZ").to_string();
+    let mut quarantine = repo.path.clone().into_os_string();
+    quarantine.push(format!(".corrupt-{stamp}"));
+    let quarantine = PathBuf::from(quarantine);
+    fs::rename(&repo.path, &quarantine)?;
+    warn!("Moved corrupted checkout {} to {}", repo.name(), quarantine.display());
+
+    if let Some(shared) = &repo.shared_clone {
+        let shared_repo = RepoCfg { path: shared.clone(), ..repo.clone() };
+        execute_git_command(&shared_repo, &["worktree", "prune"])?;
+    }
+    clone_if_missing(&RepoCfg { url: Some(url), ..repo.clone() })?;
+    Ok(quarantine)
+}
+
+/// Whether the repo's path holds a git checkout (or, for mirrors, a bare repo)
+pub fn is_checkout(repo: &RepoCfg) -> bool {
+    // A worktree's `.git` is a file, a mirror is a bare repo without one
+    repo.path.join(".git").exists() || (repo.mode == Mode::Mirror && repo.path.join("HEAD").is_file())
+}
+
+/// Clone the repository into its configured path when the path is missing or empty
+pub fn clone_if_missing(repo: &RepoCfg) -> Result<()> {
+    let is_empty = match fs::read_dir(&repo.path) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => true,
+    };
+    if !is_empty && is_checkout(repo) {
+        return Ok(());
+    }
+    let (true, Some(url)) = (is_empty, &repo.url) else {
+        return Err(super::errors::WatchError::NotACheckout(repo.path.display().to_string()));
+    };
+    let url = &apply_host_alias(repo, url);
+
+    if let Some(shared) = &repo.shared_clone {
+        return add_worktree(repo, shared, url);
+    }
+
+    info!("Cloning {} into {}", url, repo.name());
+    fs::create_dir_all(&repo.path)?;
+    let mut args = remote_config_args(url);
+    args.push("clone");
+    let filter = filter_arg(repo);
+    args.extend(filter.as_deref());
+    if repo.mode == Mode::Mirror {
+        args.push("--mirror");
+    } else if let Some(branch) = repo.branch.as_deref().filter(|b| *b != "HEAD" && repo.ref_glob.is_none()) {
+        args.extend(["--branch", branch]);
+    }
+    args.extend([url.as_str(), "."]);
+    execute_git_with_progress(repo, &args)
+}
+
+/// Check out the repo's branch as a worktree of the shared clone, cloning that first if needed
+fn add_worktree(repo: &RepoCfg, shared: &Path, url: &str) -> Result<()> {
+    let shared_repo = RepoCfg { path: shared.to_path_buf(), ..repo.clone() };
+    if !shared.join(".git").exists() {
+        info!("Cloning {} into shared clone {}", url, shared.display());
+        fs::create_dir_all(shared)?;
+        let mut args = remote_config_args(url);
+        let filter = filter_arg(repo);
+        args.extend(["clone", "--no-checkout"]);
+        args.extend(filter.as_deref());
+        args.extend([url, "."]);
+        execute_git_with_progress(&shared_repo, &args)?;
+    }
+
+    info!("Adding worktree {} for branch '{}'", repo.name(), repo.branch());
+    let remote_ref = format!("origin/{}", repo.branch());
+    let mut args = remote_config_args(url);
+    args.extend(["fetch", "origin", repo.branch()]);
+    remote_git_output(&shared_repo, &args)?;
+    let worktree = repo.path.display().to_string();
+    execute_git_command(&shared_repo, &["worktree", "add", "--force", "--detach", &worktree, &remote_ref])
+}
+
+/// What a single check found
+pub struct CheckOutcome {
+    /// Time spent fetching from the remote
+    pub fetch_time: Duration,
+    /// Set when the checkout was moved to a new commit
+    pub update: Option<UpdateEvent>,
+}
+
+/// Details of an applied update
+pub struct UpdateEvent {
+    /// Ref that was fetched, which differs from `branch` when `ref_glob` is used
+    pub branch: String,
+    pub old_sha: String,
+    pub new_sha: String,
+    /// What started the check: "poll", "webhook", "manual", "run-once" or "simulate"
+    pub trigger: &'static str,
+    /// Short diffstat plus the top-level directories touched
+    pub diffstat: String,
 }
 
-pub fn start_watching(repo: &RepoCfg) -> Result<()> {
+/// What hooks are told about an update. Mirrors have no single old and new
+/// commit, their SHAs are left empty.
+pub fn hook_vars(repo: &RepoCfg, branch: &str, old_sha: &str, new_sha: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("RUSTPDATER_OLD_SHA", old_sha.to_string()),
+        ("RUSTPDATER_NEW_SHA", new_sha.to_string()),
+        ("RUSTPDATER_BRANCH", branch.to_string()),
+        ("RUSTPDATER_REPO_PATH", repo.path.display().to_string()),
+    ]
+}
+
+/// Check the repo for updates and apply one if found. Any failure, from the
+/// fetch to the hooks, runs `on_failure` before it is returned.
+pub fn start_watching(repo: &RepoCfg, trigger: &'static str) -> Result<CheckOutcome> {
+    // Filled in as the check learns which commits it is moving between
+    let mut failure_vars = hook_vars(repo, repo.branch(), "", "");
+    let outcome = check_for_update(repo, trigger, &mut failure_vars);
+    if let Err(error) = &outcome {
+        report_failure(repo, error, &failure_vars);
+    }
+    outcome
+}
+
+fn check_for_update(repo: &RepoCfg, trigger: &'static str, failure_vars: &mut Vec<(&'static str, String)>) -> Result<CheckOutcome> {
     info!("_ _ _ _ _ _ _ _ _ _  WATCHING  _ _ _ _ _ _ _ _ _ _");
-    info!("Checking repo {} for updates", repo.path.display());
+    info!("Checking repo {} for updates", repo.name());
 
     // Get and normalize the remote URL
     let remote_url = get_remote_url(&repo.path)?;
-    let normalized_url = normalize_git_url(&remote_url);
+    let normalized_url = apply_host_alias(repo, &normalize_git_url(&remote_url));
     info!("Original remote URL: {}", remote_url);
     info!("Normalized URL: {}", normalized_url);
 
+    if repo.mode == Mode::Mirror {
+        return sync_mirror(repo, &normalized_url, trigger, failure_vars);
+    }
+
     // Fetch with authentication (SSH agent will be used automatically), using the normalized URL
-    info!("Fetching '{}' for {} using normalized URL", repo.branch, repo.path.display());
-    execute_git_command(&repo.path, &["fetch", &normalized_url, &repo.branch])?;
+    let fetch_started = Instant::now();
+    let branch = resolve_ref(repo, &normalized_url)?;
+    info!("Fetching '{}' for {} using normalized URL", branch, repo.name());
+    let fetch_head = fetch_ref(repo, &normalized_url, &branch)?;
+    let fetch_time = fetch_started.elapsed();
 
-    // Get current HEAD and FETCH_HEAD
-    let local_head = get_current_head(&repo.path)?;
-    let fetch_head = get_fetch_head(&repo.path)?;
+    // Get current HEAD to compare with the freshly fetched tracking ref
+    let local_head = current_head(&repo.path)?;
+    *failure_vars = hook_vars(repo, &branch, &local_head, &fetch_head);
 
     // If there's nothing new, escape
     if fetch_head == local_head {
-        info!("No changes detected for {}", repo.path.display());
+        if repo.reconcile {
+            drift::reconcile(repo)?;
+        }
+        info!("No changes detected for {}", repo.name());
+        info!("_ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _");
+        return Ok(CheckOutcome { fetch_time, update: None });
+    }
+
+    if was_rolled_back(repo, &fetch_head)? {
+        info!("_ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _");
+        return Ok(CheckOutcome { fetch_time, update: None });
+    }
+
+    if !commit_rules_allow(repo, &local_head, &fetch_head)? {
+        info!("_ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _");
+        return Ok(CheckOutcome { fetch_time, update: None });
+    }
+
+    if !pre_update_allows(repo, &branch, &local_head, &fetch_head) {
         info!("_ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _");
+        return Ok(CheckOutcome { fetch_time, update: None });
+    }
+
+    let lock = match &repo.deploy_lock {
+        Some(cfg) => match deploy_lock::acquire(repo, cfg, &normalized_url)? {
+            Some(lock) => Some(lock),
+            None => {
+                info!("Every deploy slot for {} is taken, retrying on the next check", repo.name());
+                info!("_ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _");
+                return Ok(CheckOutcome { fetch_time, update: None });
+            }
+        },
+        None => None,
+    };
+
+    let updated = apply_update(repo, &normalized_url, branch, local_head, fetch_head, trigger);
+    if let Some(lock) = lock {
+        deploy_lock::release(repo, &normalized_url, lock);
+    }
+    let event = updated?;
+    if !repo.commit_rules.is_empty() {
+        clear_approval(repo, &event.new_sha)?;
+    }
+
+    info!("_ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _");
+    Ok(CheckOutcome { fetch_time, update: Some(event) })
+}
+
+/// Whether `sha` is the commit an earlier `on_change` failure was rolled back from
+fn was_rolled_back(repo: &RepoCfg, sha: &str) -> Result<bool> {
+    let rolled_back = fs::read_to_string(git_dir(&repo.path)?.join(ROLLED_BACK_FILE)).unwrap_or_default();
+    if rolled_back.trim() != sha {
+        return Ok(false);
+    }
+    info!("Not deploying {} to {} again, it was rolled back; waiting for a newer commit", sha, repo.name());
+    Ok(true)
+}
+
+/// Run `pre_update` for a move from `old` to `new`; false when it vetoed the move
+fn pre_update_allows(repo: &RepoCfg, branch: &str, old: &str, new: &str) -> bool {
+    let Some(hook) = &repo.pre_update else {
+        return true;
+    };
+    match hooks::run_pre_update(repo, hook, &hook_vars(repo, branch, old, new)) {
+        Ok(()) => true,
+        Err(e) => {
+            // A veto is not a failed check, its output only matters in the log
+            hooks::take_failed_output(&repo.path);
+            info!("pre_update of {} vetoed the update to {}, retrying on the next check: {}", repo.name(), new, e);
+            false
+        }
+    }
+}
+
+/// Fetch `branch` into its tracking ref and return the commit it points to
+fn fetch_ref(repo: &RepoCfg, url: &str, branch: &str) -> Result<String> {
+    let mut args = remote_config_args(url);
+    args.push("fetch");
+    args.extend(tag_args(repo));
+    // Store the ref instead of relying on FETCH_HEAD, which holds every ref a fetch brought
+    let tracking = tracking_ref(repo, branch);
+    let refspec = format!("+{branch}:{tracking}");
+    args.extend([fetch_source(repo, url), &refspec]);
+    let mut attempt = 0;
+    loop {
+        match remote_git_output(repo, &args) {
+            Err(super::errors::WatchError::GitCommandFailed { stderr, .. }) if stderr.contains("couldn't find remote ref") => {
+                return Err(super::errors::WatchError::BranchMissing {
+                    branch: branch.to_string(),
+                    path: repo.name().to_string(),
+                });
+            }
+            Err(error) if !is_corruption(&error) && repo.retry.as_ref().is_some_and(|retry| attempt < retry.max_retries) => {
+                attempt += 1;
+                let delay = retry_delay(repo.retry.as_ref().unwrap(), attempt);
+                warn!("Fetch for {} failed, retry {} in {}ms: {}", repo.name(), attempt, delay.as_millis(), error);
+                std::thread::sleep(delay);
+            }
+            result => {
+                result?;
+                break;
+            }
+        }
+    }
+    get_tracking_head(&repo.path, &tracking)
+}
+
+/// Exponential backoff from `base_delay`, capped at `max_delay`, with up to
+/// a quarter of jitter so hosts that failed together don't retry together
+fn retry_delay(retry: &RetryCfg, attempt: u32) -> Duration {
+    let base = parse_duration(&retry.base_delay).unwrap_or(Duration::from_secs(1));
+    let max = parse_duration(&retry.max_delay).unwrap_or(Duration::from_secs(60));
+    let delay = base.saturating_mul(1 << attempt.saturating_sub(1).min(16)).min(max);
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+    delay.mul_f64(1.0 - f64::from(nanos % 1000) / 4000.0)
+}
+
+/// Wait `settle` after moving the checkout and fetch again, following further
+/// commits until the branch stays put, so a push train runs the hooks once.
+/// Returns the commit the checkout ends up on.
+fn settle(repo: &RepoCfg, url: &str, branch: &str, mut head: String, settle: Duration) -> Result<String> {
+    loop {
+        info!("Waiting {}s for {} to settle before running hooks", settle.as_secs(), repo.name());
+        std::thread::sleep(settle);
+        let latest = fetch_ref(repo, url, branch)?;
+        // Later commits get the same vetting as the first, or settling stops where it is
+        if latest == head
+            || was_rolled_back(repo, &latest)?
+            || !commit_rules_allow(repo, &head, &latest)?
+            || !pre_update_allows(repo, branch, &head, &latest)
+        {
+            return Ok(head);
+        }
+        if !is_fast_forward(repo, &head, &latest)? {
+            if repo.strategy == Strategy::FfOnly {
+                // The next check reports the rewrite, the hooks still run for what is checked out
+                warn!("Branch '{}' of {} was rewritten upstream while settling, staying on {}", branch, repo.name(), head);
+                return Ok(head);
+            }
+            warn!("Branch '{}' of {} was rewritten upstream while settling, resetting to it", branch, repo.name());
+        }
+        info!("More commits arrived for {}, moving on to {}", repo.name(), latest);
+        execute_git_command(repo, &["reset", "--hard", &latest])?;
+        head = latest;
+    }
+}
+
+/// Full name of a ref that belongs to this checkout alone: under `refs/` for a
+/// clone of its own, under the per-worktree `refs/worktree/` for the branch
+/// checkouts sharing one clone, so they don't overwrite each other's
+fn local_ref(repo: &RepoCfg, name: &str) -> String {
+    match repo.shared_clone {
+        Some(_) => format!("refs/worktree/{name}"),
+        None => format!("refs/{name}"),
+    }
+}
+
+/// Whether moving from `from` to `to` only adds commits
+fn is_fast_forward(repo: &RepoCfg, from: &str, to: &str) -> Result<bool> {
+    Ok(git_command(repo)
+        .args(["merge-base", "--is-ancestor", from, to])
+        .current_dir(&repo.path)
+        .status()?
+        .success())
+}
+
+/// Move the checkout to the fetched commit and run the deploy steps
+fn apply_update(
+    repo: &RepoCfg,
+    normalized_url: &str,
+    branch: String,
+    local_head: String,
+    fetch_head: String,
+    trigger: &'static str,
+) -> Result<UpdateEvent> {
+    let fast_forward = is_fast_forward(repo, &local_head, &fetch_head)?;
+    if !fast_forward && repo.strategy == Strategy::FfOnly {
+        return Err(super::errors::WatchError::NotFastForward { branch, path: repo.name().to_string() });
+    }
+
+    // Anchor the old commit for rollbacks, independent of the reflog
+    execute_git_command(repo, &["update-ref", "-m", "rustpdater: before update", &local_ref(repo, PREVIOUS_REF), &local_head])?;
+    if repo.backup_refs {
+        let backup = local_ref(repo, &format!("{BACKUP_REF_PREFIX}{}", jiff::Timestamp::now().strftime("%Y%m%dT%H%M%SZ")));
+        execute_git_command(repo, &["update-ref", &backup, &local_head])?;
+    }
+
+    if fast_forward {
+        info!("Fast-forwarding repo {} to new HEAD", repo.name());
+    } else {
+        warn!("Branch '{}' of {} was rewritten upstream, resetting to it", branch, repo.name());
+    }
+    let stashed = repo.autostash && stash_local_changes(repo)?;
+    execute_git_command(repo, &["reset", "--hard", &fetch_head])?;
+    let settled = match repo.settle.as_deref().and_then(super::duration::parse_duration) {
+        Some(duration) => settle(repo, normalized_url, &branch, fetch_head, duration),
+        None => Ok(fetch_head),
+    };
+    // Only once settle's own resets are done, they would throw the popped changes away
+    if stashed {
+        reapply_stash(repo)?;
+    }
+    let fetch_head = settled?;
+
+    let changed_output = git_output(repo, &["diff", "--name-only", &local_head, &fetch_head])?;
+    let changed_files: Vec<&str> = changed_output.lines().collect();
+    let event = UpdateEvent {
+        diffstat: diff_summary(repo, &local_head, &fetch_head, &changed_files)?,
+        branch,
+        old_sha: local_head,
+        new_sha: fetch_head,
+        trigger,
+    };
+    info!("Updated {}: {}", repo.name(), event.diffstat);
+    if let Some(tags) = &repo.deploy_tags {
+        deploy_tags::tag(repo, tags, &event)?;
+    }
+
+    let deployed = deploy(repo, normalized_url, &event, &changed_files);
+    if let Some(github) = &repo.github_status {
+        github::report(repo, github, normalized_url, &event.new_sha, deployed.is_ok());
+    }
+    deployed?;
+    Ok(event)
+}
+
+/// Run `on_failure` for a failed check or clone. Its own failure is only
+/// logged, the original error is what gets reported.
+pub fn report_failure(repo: &RepoCfg, error: &super::errors::WatchError, vars: &[(&'static str, String)]) {
+    let Some(hook) = &repo.on_failure else {
+        return;
+    };
+    let mut vars = vars.to_vec();
+    vars.push(("RUSTPDATER_ERROR", error.to_string()));
+    if let Err(e) = hooks::run_on_failure(repo, hook, &vars) {
+        warn!("on_failure hook for {} failed too: {}", repo.name(), e);
+    }
+}
+
+/// Stash uncommitted changes to tracked files; false when there were none.
+/// Untracked files are left in place, the reset doesn't touch them either.
+fn stash_local_changes(repo: &RepoCfg) -> Result<bool> {
+    if git_output(repo, &["status", "--porcelain", "--untracked-files=no"])?.trim().is_empty() {
+        return Ok(false);
+    }
+    let message = format!("rustpdater autostash {}", jiff::Timestamp::now());
+    git_output(repo, &["stash", "push", "-m", &message])?;
+    info!("Stashed local changes of {} as '{}'", repo.name(), message);
+    Ok(true)
+}
+
+/// Pop the autostash onto the updated checkout. On conflicts the checkout is
+/// put back to the update and the changes stay in the stash for a human.
+fn reapply_stash(repo: &RepoCfg) -> Result<()> {
+    match git_output(repo, &["stash", "pop"]) {
+        Ok(_) => {
+            info!("Reapplied local changes of {}", repo.name());
+            Ok(())
+        }
+        // git reports the conflicting files on stdout, which the error doesn't carry
+        Err(_) => {
+            git_output(repo, &["reset", "--hard", "HEAD"])?;
+            warn!("Local changes of {} conflict with the update and were kept in `git stash list`", repo.name());
+            Ok(())
+        }
+    }
+}
+
+/// Everything that follows moving the checkout: marker, Terraform, hooks and push-back
+fn deploy(repo: &RepoCfg, normalized_url: &str, event: &UpdateEvent, changed_files: &[&str]) -> Result<()> {
+    if let Some(deploy_marker) = &repo.deploy_marker {
+        marker::write_deploy_marker(repo, deploy_marker, event)?;
+    }
+
+    // Our own push_back commit landing on the watched branch only needs the checkout moved
+    let message = git_output(repo, &["log", "-1", "--format=%B", &event.new_sha])?;
+    if message.lines().any(|line| line.trim() == GENERATED_TRAILER) {
+        info!("Skipping hooks for {}: new HEAD is a push_back commit", repo.name());
         return Ok(());
     }
 
-    // Reset to the new HEAD (fast-forward)
-    info!("Fast-forwarding repo {} to new HEAD", repo.path.display());
-    execute_git_command(&repo.path, &["reset", "--hard", &fetch_head])?;
+    if let Some(terraform) = &repo.terraform {
+        terraform::plan_and_apply(repo, terraform)?;
+    }
 
-    if let Some(cmd) = &repo.on_change {
-        info!("Running on_change hook for {}: {}", repo.path.display(), cmd);
-        Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .current_dir(&repo.path)
-            .status()?;
+    let vars = hook_vars(repo, &event.branch, &event.old_sha, &event.new_sha);
+    if let Some(hook) = &repo.on_change {
+        if let Err(error) = hooks::run_on_change(repo, hook, &vars) {
+            if repo.rollback_on_hook_failure {
+                roll_back(repo, event)?;
+            }
+            return Err(error);
+        }
+    }
+
+    run_path_hooks(repo, changed_files, &vars)?;
+
+    if let Some(push_back) = &repo.push_back {
+        push_back_generated(repo, push_back, normalized_url, &event.new_sha)?;
+    }
+    Ok(())
+}
+
+/// Put the checkout back on the commit before a failed deploy and remember
+/// the failed one, so later checks wait for a fix instead of retrying it
+fn roll_back(repo: &RepoCfg, event: &UpdateEvent) -> Result<()> {
+    warn!("on_change failed for {}, rolling back from {} to {}", repo.name(), event.new_sha, event.old_sha);
+    execute_git_command(repo, &["reset", "--hard", &event.old_sha])?;
+    fs::write(git_dir(&repo.path)?.join(ROLLED_BACK_FILE), format!("{}\n", event.new_sha))?;
+    if let Some(deploy_marker) = &repo.deploy_marker {
+        let rollback = UpdateEvent {
+            branch: event.branch.clone(),
+            old_sha: event.new_sha.clone(),
+            new_sha: event.old_sha.clone(),
+            trigger: "rollback",
+            diffstat: format!("rolled back {}", event.new_sha),
+        };
+        marker::write_deploy_marker(repo, deploy_marker, &rollback)?;
+    }
+    Ok(())
+}
+
+/// Bring a bare mirror in line with the remote: every ref, deleted ones pruned, no checkout
+fn sync_mirror(repo: &RepoCfg, url: &str, trigger: &'static str, failure_vars: &mut Vec<(&'static str, String)>) -> Result<CheckOutcome> {
+    let refs_before = git_output(repo, &["for-each-ref", "--format=%(objectname) %(refname)"])?;
+
+    let fetch_started = Instant::now();
+    let mut args = remote_config_args(url);
+    args.extend(["fetch", "--prune", fetch_source(repo, url), "+refs/*:refs/*"]);
+    remote_git_output(repo, &args)?;
+    let fetch_time = fetch_started.elapsed();
+
+    let refs_after = git_output(repo, &["for-each-ref", "--format=%(objectname) %(refname)"])?;
+    let parse = |listing: &str| -> BTreeMap<String, String> {
+        listing
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(sha, name)| (name.to_string(), sha.to_string()))
+            .collect()
+    };
+    let (before, after) = (parse(&refs_before), parse(&refs_after));
+    let changed = before
+        .keys()
+        .chain(after.keys().filter(|name| !before.contains_key(*name)))
+        .filter(|name| before.get(*name) != after.get(*name))
+        .count();
+
+    if changed == 0 {
+        info!("No changes detected for mirror {}", repo.name());
+        info!("_ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _");
+        return Ok(CheckOutcome { fetch_time, update: None });
+    }
+
+    let event = UpdateEvent {
+        branch: "refs/*".to_string(),
+        old_sha: String::new(),
+        new_sha: String::new(),
+        trigger,
+        diffstat: format!("{changed} refs changed"),
+    };
+    info!("Updated mirror {}: {}", repo.name(), event.diffstat);
+
+    if let Some(hook) = &repo.on_change {
+        *failure_vars = hook_vars(repo, &event.branch, "", "");
+        hooks::run_on_change(repo, hook, failure_vars)?;
     }
 
     info!("_ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _");
+    Ok(CheckOutcome { fetch_time, update: Some(event) })
+}
+
+/// Commit the generated files on top of `head` and push them to the push_back branch.
+/// A scratch index is used so the checkout's HEAD and index are left untouched.
+fn push_back_generated(repo: &RepoCfg, cfg: &PushBackCfg, url: &str, head: &str) -> Result<()> {
+    let index = git_path(&repo.path, "rustpdater-push-back-index")?;
+    let run = |args: &[&str]| -> Result<String> {
+        let output = git_command(repo)
+            .args(args)
+            .env("GIT_INDEX_FILE", &index)
+            .current_dir(&repo.path)
+            .output()?;
+        if !output.status.success() {
+            return Err(super::errors::WatchError::GitCommandFailed {
+                command: format!("git {}", args.join(" ")),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            });
+        }
+        Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    };
+
+    let pathspecs: Vec<String> = cfg.paths.iter().map(|p| format!(":(glob){p}")).collect();
+    let mut add = vec!["add", "--all", "--force", "--"];
+    add.extend(pathspecs.iter().map(String::as_str));
+    let tree = run(&["read-tree", head]).and_then(|_| run(&add)).and_then(|_| run(&["write-tree"]));
+    let _ = fs::remove_file(&index);
+    let tree = tree?;
+
+    if tree == git_output(repo, &["rev-parse", &format!("{head}^{{tree}}")])? {
+        info!("No generated changes to push back for {}", repo.name());
+        return Ok(());
+    }
+
+    let message = format!("Update generated files\n\n{GENERATED_TRAILER}");
+    // Daemon hosts rarely have an identity configured, and commit-tree refuses to guess one
+    let commit = git_output(repo, &[
+        "-c", "user.name=rustpdater",
+        "-c", "user.email=rustpdater@localhost",
+        "commit-tree", &tree, "-p", head, "-m", &message,
+    ])?;
+
+    // The generated branch is rebuilt on top of every deploy, unless it is the watched branch itself
+    let refspec = format!("{commit}:refs/heads/{}", cfg.branch);
+    let mut args = remote_config_args(url);
+    args.extend(["push", url, &refspec]);
+    if cfg.branch != repo.branch() {
+        args.push("--force");
+    }
+    remote_git_output(repo, &args)?;
+    info!("Pushed generated files for {} to {}", repo.name(), cfg.branch);
+    Ok(())
+}
+
+/// Summarise an update as "N files changed, X insertions(+), Y deletions(-); touched: a, b"
+fn diff_summary(repo: &RepoCfg, old_head: &str, new_head: &str, changed_files: &[&str]) -> Result<String> {
+    const MAX_DIRS: usize = 5;
+
+    let shortstat = git_output(repo, &["diff", "--shortstat", old_head, new_head])?;
+    let mut dirs: Vec<&str> = changed_files
+        .iter()
+        .map(|file| file.split_once('/').map_or(".", |(dir, _)| dir))
+        .collect();
+    dirs.sort_unstable();
+    dirs.dedup();
+
+    let mut touched = dirs.iter().take(MAX_DIRS).copied().collect::<Vec<_>>().join(", ");
+    if dirs.len() > MAX_DIRS {
+        touched.push_str(&format!(" and {} more", dirs.len() - MAX_DIRS));
+    }
+
+    if shortstat.is_empty() {
+        return Ok("no file changes".to_string());
+    }
+    Ok(format!("{shortstat}; touched: {touched}"))
+}
+
+/// Apply `commit_rules` to the incoming commits; false means the update is held for approval
+fn commit_rules_allow(repo: &RepoCfg, old_head: &str, new_head: &str) -> Result<bool> {
+    if repo.commit_rules.is_empty() {
+        return Ok(true);
+    }
+
+    let log = git_output(repo, &["log", "--format=%B%x1e", &format!("{old_head}..{new_head}")])?;
+    let messages: Vec<String> = log
+        .split('\x1e')
+        .map(|message| message.trim().to_string())
+        .filter(|message| !message.is_empty())
+        .collect();
+    let (action, label) = commit_rules::evaluate(&repo.commit_rules, &messages);
+    if action == CommitAction::Deploy {
+        return Ok(true);
+    }
+
+    let git_dir = git_dir(&repo.path)?;
+    let approved = fs::read_to_string(git_dir.join(APPROVED_FILE)).unwrap_or_default();
+    if approved.trim() == new_head {
+        // Kept until the update went through, a veto or a busy deploy slot must not use it up
+        info!("Held update of {} to {} was approved", repo.name(), new_head);
+        return Ok(true);
+    }
+
+    fs::write(git_dir.join(HELD_FILE), format!("{new_head}\n"))?;
+    warn!(
+        "Update of {} to {} held for approval ({}); run `rustpdater approve {}`",
+        repo.name(),
+        new_head,
+        label.unwrap_or_default(),
+        repo.name()
+    );
+    Ok(false)
+}
+
+/// Forget the approval and the hold once `deployed` is in place
+fn clear_approval(repo: &RepoCfg, deployed: &str) -> Result<()> {
+    let git_dir = git_dir(&repo.path)?;
+    let _ = fs::remove_file(git_dir.join(APPROVED_FILE));
+    if fs::read_to_string(git_dir.join(HELD_FILE)).is_ok_and(|held| held.trim() == deployed) {
+        let _ = fs::remove_file(git_dir.join(HELD_FILE));
+    }
+    Ok(())
+}
+
+/// Approve the update currently held by `commit_rules`, returning its commit
+pub fn approve_held_update(repo: &RepoCfg) -> Result<String> {
+    let git_dir = git_dir(&repo.path)?;
+    let held = fs::read_to_string(git_dir.join(HELD_FILE))
+        .map_err(|_| super::errors::WatchError::NothingHeld(repo.name().to_string()))?;
+    fs::write(git_dir.join(APPROVED_FILE), &held)?;
+    Ok(held.trim().to_string())
+}
+
+/// Rehearse an update: move the checkout back to `from`, run a normal check
+/// against the remote from there, then return to the original commit when
+/// `restore` is set. The restore only moves the checkout, it runs no hooks.
+pub fn simulate(repo: &RepoCfg, from: &str, restore: bool) -> Result<CheckOutcome> {
+    let original = current_head(&repo.path)?;
+    let from = git_output(repo, &["rev-parse", "--verify", &format!("{from}^{{commit}}")])?;
+    info!("Rewinding {} to {} to rehearse an update", repo.name(), from);
+    execute_git_command(repo, &["reset", "--hard", &from])?;
+
+    let outcome = start_watching(repo, "simulate");
+    if restore {
+        info!("Restoring {} to {}", repo.name(), original);
+        execute_git_command(repo, &["reset", "--hard", &original])?;
+    }
+    outcome
+}
+
+/// Run the hooks whose path globs match one of the changed files
+fn run_path_hooks(repo: &RepoCfg, changed_files: &[&str], vars: &[(&str, String)]) -> Result<()> {
+    for hook in &repo.hooks {
+        let affected = changed_files
+            .iter()
+            .any(|file| hook.paths.iter().any(|pattern| glob_match(pattern, file)));
+        if affected {
+            info!("Path hook for {} matched {}", repo.name(), hook.paths.join(", "));
+            hooks::run_path_hook(repo, hook, vars)?;
+        }
+    }
     Ok(())
 }
 
 /// Test git pull in a temporary folder to verify git operations work
-pub fn test_git_pull_in_tmp(repo_path: &Path) -> Result<()> {
+pub fn test_git_pull_in_tmp(repo: &RepoCfg) -> Result<()> {
     info!("_ _ _ _ _ _ _ _ _ _  TESTING GIT OPERATIONS  _ _ _ _ _ _ _ _ _ _");
     info!("Testing git pull in a temporary folder to verify git operations work");
 
 
     // Get the remote URL from the existing repository
-    let remote_url = get_remote_url(repo_path)?;
+    let remote_url = get_remote_url(&repo.path)?;
 
     // Normalize the URL to fix malformed URLs
-    let normalized_url = normalize_git_url(&remote_url);
+    let normalized_url = apply_host_alias(repo, &normalize_git_url(&remote_url));
     info!("Original remote URL: {}", remote_url);
     info!("Normalized URL: {}", normalized_url);
 
@@ -168,17 +1378,15 @@ pub fn test_git_pull_in_tmp(repo_path: &Path) -> Result<()> {
 
     // Clone the repository into the temp directory
     info!("Testing git pull by cloning {} into temporary directory", normalized_url);
-    let clone_output = Command::new("git")
-        .args(["clone", &normalized_url, temp_dir.to_str().unwrap()])
-        .output()?;
-
-    if !clone_output.status.success() {
-        let stderr = String::from_utf8_lossy(&clone_output.stderr);
-        warn!("Git clone failed: {}", stderr);
-        return Err(super::errors::WatchError::GitCommandFailed {
-            command: format!("git clone {}", normalized_url),
-            stderr: stderr.to_string(),
-        });
+    let mut args = remote_config_args(&normalized_url);
+    let filter = filter_arg(repo);
+    args.extend(["clone", "--no-checkout"]);
+    args.extend(filter.as_deref());
+    args.extend([normalized_url.as_str(), temp_dir.to_str().unwrap()]);
+    if let Err(e) = remote_git_output(repo, &args) {
+        warn!("Git clone failed: {}", e);
+        let _ = fs::remove_dir_all(&temp_dir);
+        return Err(e);
     }
 
     // Clean up the temporary directory
@@ -0,0 +1,111 @@
+use super::errors::{Result, WatchError};
+use super::repo_config::{GithubStatusCfg, RepoCfg};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use log::{info, warn};
+use serde_json::{json, Value};
+
+/// Tell GitHub where a commit now runs: a commit status, or a deployment with
+/// its status when an environment is configured. Failures are only logged,
+/// reporting never blocks an update.
+pub fn report(repo: &RepoCfg, cfg: &GithubStatusCfg, url: &str, sha: &str, success: bool) {
+    if let Err(e) = try_report(cfg, url, sha, success) {
+        warn!("Could not report the deployment of {} to GitHub: {}", repo.name(), e);
+    }
+}
+
+fn try_report(cfg: &GithubStatusCfg, url: &str, sha: &str, success: bool) -> Result<()> {
+    let slug = match &cfg.repo {
+        Some(slug) => slug.clone(),
+        None => repo_slug(url).ok_or_else(|| WatchError::CommandFailed {
+            command: "github status".to_string(),
+            stderr: format!("cannot tell the GitHub repository from {url}, set github_status.repo"),
+        })?,
+    };
+    let token = std::env::var(&cfg.token_env).map_err(|_| WatchError::CommandFailed {
+        command: "github status".to_string(),
+        stderr: format!("{} is not set", cfg.token_env),
+    })?;
+    let host = hostname();
+    let api = format!("{}/repos/{}", cfg.api_url.trim_end_matches('/'), slug);
+
+    match &cfg.environment {
+        Some(environment) => {
+            let deployment = post(&token, &format!("{api}/deployments"), &json!({
+                "ref": sha,
+                "environment": environment,
+                "auto_merge": false,
+                "required_contexts": [],
+                "description": format!("rustpdater on {host}"),
+            }))?;
+            let id = deployment["id"].as_u64().ok_or_else(|| WatchError::CommandFailed {
+                command: "github deployment".to_string(),
+                stderr: format!("unexpected response: {deployment}"),
+            })?;
+            post(&token, &format!("{api}/deployments/{id}/statuses"), &json!({
+                "state": if success { "success" } else { "failure" },
+                "environment": environment,
+                "description": format!("Deployed on {host}"),
+            }))?;
+            info!("Reported deployment {} of {} to {} ({})", id, sha, slug, environment);
+        }
+        None => {
+            post(&token, &format!("{api}/statuses/{sha}"), &json!({
+                "state": if success { "success" } else { "failure" },
+                "context": format!("rustpdater/{host}"),
+                "description": if success { format!("Deployed on {host}") } else { format!("Deploy failed on {host}") },
+            }))?;
+            info!("Reported commit status of {} to {}", sha, slug);
+        }
+    }
+    Ok(())
+}
+
+/// POST a JSON body with curl, handing the token over stdin so it stays out of argv
+fn post(token: &str, url: &str, body: &Value) -> Result<Value> {
+    let mut child = Command::new("curl")
+        .args(["-sS", "-m", "10", "--fail-with-body", "-X", "POST", "-H", "@-"])
+        .args(["-H", "Accept: application/vnd.github+json", "--data-binary", &body.to_string(), url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "Authorization: Bearer {token}")?;
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(WatchError::CommandFailed {
+            command: format!("curl POST {url}"),
+            stderr: format!("{}{}", String::from_utf8_lossy(&output.stderr), String::from_utf8_lossy(&output.stdout)),
+        });
+    }
+    Ok(serde_json::from_slice(&output.stdout).unwrap_or(Value::Null))
+}
+
+/// "owner/name" of a github.com remote in https, ssh:// or scp-like form
+fn repo_slug(url: &str) -> Option<String> {
+    let rest = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('/'),
+        None => url.split_once(':'),
+    };
+    let (host, path) = rest?;
+    if !host.ends_with("github.com") {
+        return None;
+    }
+    let slug = path.trim_matches('/').trim_end_matches(".git");
+    (slug.split('/').count() == 2).then(|| slug.to_string())
+}
+
+/// Name of this machine, as reported in statuses and locks
+pub fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length and gethostname NUL-terminates within it
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return "unknown-host".to_string();
+    }
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..end]).to_string()
+}
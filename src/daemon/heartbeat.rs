@@ -0,0 +1,27 @@
+use super::repo_config::RepoCfg;
+use std::process::{Command, Stdio};
+use log::warn;
+
+/// Ping a healthchecks.io-style URL: the URL itself after a good check, `<url>/fail`
+/// after a failed one. A missed ping is only logged; the monitoring side alerts
+/// when pings stop, which is the point.
+pub fn ping(repo: &RepoCfg, url: &str, success: bool) {
+    let target = if success {
+        url.to_string()
+    } else {
+        format!("{}/fail", url.trim_end_matches('/'))
+    };
+    let result = Command::new("curl")
+        .args(["-fsS", "-m", "10", "--retry", "2", "-o", "/dev/null", &target])
+        .stdin(Stdio::null())
+        .output();
+    match result {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => warn!(
+            "Heartbeat ping for {} failed: {}",
+            repo.name(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => warn!("Could not run curl for the heartbeat of {}: {}", repo.name(), e),
+    }
+}
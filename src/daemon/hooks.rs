@@ -0,0 +1,241 @@
+use super::duration::parse_duration;
+use super::errors::{Result, WatchError};
+use super::limits;
+use super::priority;
+use super::repo_config::{HookShell, HookStep, OnChange, PathHook, RepoCfg, StepCommand};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use log::{info, warn};
+
+/// How often a step with a timeout is checked for completion
+const POLL: Duration = Duration::from_millis(100);
+/// Lines of hook output kept for `status` when a hook fails
+const OUTPUT_TAIL_LINES: usize = 20;
+/// How long to wait for output still in the pipes once a step has exited.
+/// Processes the step left running in the background may keep them open.
+const OUTPUT_DRAIN: Duration = Duration::from_secs(1);
+
+/// Last lines of the hook that failed, by repo, until `status` picks them up
+static FAILED_OUTPUT: OnceLock<Mutex<HashMap<PathBuf, Vec<String>>>> = OnceLock::new();
+
+/// Output tail of the repo's last failed hook step, if the failure since the
+/// last call came from a hook
+pub fn take_failed_output(path: &Path) -> Option<Vec<String>> {
+    FAILED_OUTPUT.get_or_init(Default::default).lock().unwrap().remove(path)
+}
+
+/// Run the `on_change` steps in order inside the checkout, stopping at the
+/// first one that exits non-zero or runs past its timeout. `update` is the
+/// `RUSTPDATER_*` description of the update, see `git_ops::hook_vars`.
+pub fn run_on_change(repo: &RepoCfg, hook: &OnChange, update: &[(&str, String)]) -> Result<()> {
+    run_hook(repo, "on_change", hook, update)
+}
+
+/// Run a `hooks` entry whose paths matched, as a single shell step of `on_change`
+pub fn run_path_hook(repo: &RepoCfg, hook: &PathHook, update: &[(&str, String)]) -> Result<()> {
+    run_hook(repo, "path hook", &OnChange::Shell(hook.run.clone()), update)
+}
+
+/// Run the `pre_update` steps like `on_change`, before the checkout moves
+pub fn run_pre_update(repo: &RepoCfg, hook: &OnChange, update: &[(&str, String)]) -> Result<()> {
+    run_hook(repo, "pre_update", hook, update)
+}
+
+/// Run the `on_failure` steps like `on_change`, with the failed check and
+/// `RUSTPDATER_ERROR` in `failure`
+pub fn run_on_failure(repo: &RepoCfg, hook: &OnChange, failure: &[(&str, String)]) -> Result<()> {
+    run_hook(repo, "on_failure", hook, failure)
+}
+
+fn run_hook(repo: &RepoCfg, name: &str, hook: &OnChange, vars: &[(&str, String)]) -> Result<()> {
+    take_failed_output(&repo.path);
+    let steps = hook.steps();
+    for (index, step) in steps.iter().enumerate() {
+        if steps.len() > 1 {
+            info!("{} step {}/{} for {}: {}", name, index + 1, steps.len(), repo.name(), step);
+        } else {
+            info!("Running {} hook for {}: {}", name, repo.name(), step);
+        }
+        run_step(repo, name, step, vars)?;
+    }
+    Ok(())
+}
+
+fn run_step(repo: &RepoCfg, name: &str, step: &HookStep, vars: &[(&str, String)]) -> Result<()> {
+    let mut command = match step {
+        HookStep::Command(StepCommand::Shell(cmd)) | HookStep::Timed { run: StepCommand::Shell(cmd), .. } => {
+            shell_command(repo, name, cmd)?
+        }
+        HookStep::Command(StepCommand::Argv(argv)) | HookStep::Timed { run: StepCommand::Argv(argv), .. } => {
+            let (program, args) = argv.split_first().ok_or_else(|| WatchError::CommandFailed {
+                command: name.to_string(),
+                stderr: "empty argv step".to_string(),
+            })?;
+            let mut command = limits::command(repo, program);
+            command.args(args);
+            command
+        }
+        HookStep::Script { script, args, .. } => {
+            let mut argv = script_argv(repo, script)?;
+            argv.extend(args.iter().cloned());
+            let mut command = limits::command(repo, &argv[0]);
+            command.args(&argv[1..]);
+            command
+        }
+    };
+    command.envs(&repo.env).envs(vars.iter().cloned()).current_dir(&repo.path);
+    let timeout = match step.timeout() {
+        Some(timeout) => {
+            let parsed = parse_duration(timeout);
+            if parsed.is_none() {
+                warn!("Ignoring invalid hook timeout '{}' for {}", timeout, repo.name());
+            }
+            parsed
+        }
+        None => repo.hook_timeout_secs.map(Duration::from_secs),
+    };
+    let (status, lines) = run_logged(repo, command, timeout)?;
+    if status.is_none_or(|status| !status.success()) {
+        FAILED_OUTPUT.get_or_init(Default::default).lock().unwrap().insert(repo.path.clone(), lines);
+    }
+
+    match status {
+        Some(status) if status.success() => Ok(()),
+        Some(status) => Err(WatchError::HookFailed { command: step.to_string(), status: status.to_string() }),
+        None => Err(WatchError::HookTimeout {
+            command: step.to_string(),
+            timeout: step.timeout().map_or_else(|| format!("{}s", timeout.unwrap_or_default().as_secs()), str::to_string),
+        }),
+    }
+}
+
+/// Run `command` in its own process group, logging each output line under the
+/// repo's name and killing the group after `timeout`. Returns the exit status
+/// (None on timeout) and the last lines of output.
+pub fn run_logged(repo: &RepoCfg, mut command: Command, timeout: Option<Duration>) -> Result<(Option<ExitStatus>, Vec<String>)> {
+    // Own process group, so a timeout takes down everything the command started
+    command.stdout(Stdio::piped()).stderr(Stdio::piped()).process_group(0);
+    priority::apply(&mut command, repo);
+
+    let mut child = command.spawn()?;
+    let tail = Arc::new(Mutex::new(VecDeque::new()));
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(log_output(repo, stdout, tail.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(log_output(repo, stderr, tail.clone()));
+    }
+    let status = wait(child, timeout)?;
+
+    let drained = Instant::now() + OUTPUT_DRAIN;
+    while readers.iter().any(|reader| !reader.is_finished()) && Instant::now() < drained {
+        thread::sleep(POLL);
+    }
+    let lines = tail.lock().unwrap().iter().cloned().collect();
+    Ok((status, lines))
+}
+
+/// Start a hook given as one string the way the repo's `shell` says
+pub fn shell_command(repo: &RepoCfg, name: &str, cmd: &str) -> Result<Command> {
+    let shell = match &repo.shell {
+        HookShell::Enabled(true) => "sh",
+        HookShell::Program(shell) => shell,
+        HookShell::Enabled(false) => {
+            let mut words = cmd.split_whitespace();
+            let program = words.next().ok_or_else(|| WatchError::CommandFailed {
+                command: name.to_string(),
+                stderr: "empty command".to_string(),
+            })?;
+            let mut command = limits::command(repo, program);
+            command.args(words);
+            return Ok(command);
+        }
+    };
+    let mut command = limits::command(repo, shell);
+    command.arg("-c").arg(cmd);
+    Ok(command)
+}
+
+/// Log each line the step writes, prefixed with the repo, keeping the last ones in `tail`
+fn log_output(repo: &RepoCfg, output: impl Read + Send + 'static, tail: Arc<Mutex<VecDeque<String>>>) -> JoinHandle<()> {
+    let prefix = repo.name().to_string();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(output);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).is_ok_and(|read| read > 0) {
+            let text = String::from_utf8_lossy(&line).trim_end().to_string();
+            line.clear();
+            info!("[{}] {}", prefix, text);
+            let mut tail = tail.lock().unwrap();
+            if tail.len() == OUTPUT_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(text);
+        }
+    })
+}
+
+/// How to start a script from the checkout: directly when it is executable,
+/// through the interpreter named by its shebang otherwise. Scripts must stay
+/// inside the checkout.
+fn script_argv(repo: &RepoCfg, script: &Path) -> Result<Vec<String>> {
+    let failed = |reason: String| WatchError::CommandFailed { command: script.display().to_string(), stderr: reason };
+    let root = repo.path.canonicalize()?;
+    let path = root
+        .join(script)
+        .canonicalize()
+        .map_err(|e| failed(format!("not found in {}: {e}", repo.path.display())))?;
+    if !path.starts_with(&root) {
+        return Err(failed(format!("resolves outside of {}", repo.path.display())));
+    }
+    let path_str = path.display().to_string();
+
+    let metadata = fs::metadata(&path)?;
+    if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
+        return Ok(vec![path_str]);
+    }
+
+    let mut first_line = String::new();
+    BufReader::new(fs::File::open(&path)?).read_line(&mut first_line)?;
+    let Some(shebang) = first_line.strip_prefix("#!") else {
+        return Err(failed("is neither executable nor starts with a #! line".to_string()));
+    };
+    let mut argv: Vec<String> = shebang.split_whitespace().map(str::to_string).collect();
+    if argv.is_empty() {
+        return Err(failed("has an empty #! line".to_string()));
+    }
+    argv.push(path_str);
+    Ok(argv)
+}
+
+/// Wait for the child, killing its process group once `timeout` has passed (None)
+fn wait(mut child: Child, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return Ok(Some(child.wait()?));
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            // SAFETY: plain syscall on the group the child leads
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(POLL);
+    }
+}
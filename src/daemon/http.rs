@@ -0,0 +1,242 @@
+use super::config::TlsCfg;
+use super::errors::{Result, WatchError};
+use std::fs;
+use std::io::BufReader;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use log::{info, warn};
+
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+/// Largest body read, enough for a push event listing many commits
+const MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+/// How long a client gets to send its whole request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections handled at once; further ones wait in the accept backlog
+const MAX_CONNECTIONS: usize = 64;
+
+/// A parsed HTTP/1.1 request
+pub struct Request {
+    /// Address of the client that sent it
+    pub peer: IpAddr,
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    /// Value of the first header called `name` (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers.iter().find(|(key, _)| *key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+/// The response written back to the client
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn text(status: u16, body: &str) -> Response {
+        Response { status, content_type: "text/plain", body: format!("{body}\n") }
+    }
+
+    pub fn json(status: u16, body: &serde_json::Value) -> Response {
+        Response { status, content_type: "application/json", body: format!("{body:#}\n") }
+    }
+}
+
+pub type Handler = Arc<dyn Fn(Request) -> Response + Send + Sync>;
+
+/// Serve requests on `addr` forever, answering each with `handler`; over
+/// HTTPS when `tls` names a certificate and key
+pub async fn serve(addr: SocketAddr, tls: Option<TlsCfg>, handler: Handler) -> Result<()> {
+    let mut tls = tls.map(Tls::load).transpose()?;
+    let listener = TcpListener::bind(addr).await?;
+    info!("{} listener on {}", if tls.is_some() { "HTTPS" } else { "HTTP" }, addr);
+    let slots = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+
+    loop {
+        let slot = slots.clone().acquire_owned().await.expect("semaphore is never closed");
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = tls.as_mut().map(Tls::acceptor);
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let handled = match acceptor {
+                Some(acceptor) => handle_tls(stream, acceptor, peer.ip(), &handler).await,
+                None => handle_connection(stream, peer.ip(), &handler).await,
+            };
+            if let Err(e) = handled {
+                warn!("HTTP request from {} failed: {}", peer, e);
+            }
+            drop(slot);
+        });
+    }
+}
+
+/// A listener's certificate, loaded again once its files change on disk
+struct Tls {
+    cfg: TlsCfg,
+    acceptor: TlsAcceptor,
+    modified: Option<(SystemTime, SystemTime)>,
+}
+
+impl Tls {
+    fn load(cfg: TlsCfg) -> Result<Tls> {
+        let modified = modified(&cfg);
+        let acceptor = build_acceptor(&cfg)?;
+        Ok(Tls { cfg, acceptor, modified })
+    }
+
+    /// The acceptor for the next connection. A renewed certificate is picked up
+    /// here; one that doesn't load keeps the previous one in use.
+    fn acceptor(&mut self) -> TlsAcceptor {
+        let modified = modified(&self.cfg);
+        if modified != self.modified {
+            match build_acceptor(&self.cfg) {
+                Ok(acceptor) => {
+                    info!("Reloaded TLS certificate {}", self.cfg.cert.display());
+                    self.acceptor = acceptor;
+                    self.modified = modified;
+                }
+                Err(e) => warn!("Keeping the previous TLS certificate: {}", e),
+            }
+        }
+        self.acceptor.clone()
+    }
+}
+
+fn modified(cfg: &TlsCfg) -> Option<(SystemTime, SystemTime)> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    Some((modified(&cfg.cert)?, modified(&cfg.key)?))
+}
+
+fn build_acceptor(cfg: &TlsCfg) -> Result<TlsAcceptor> {
+    let failed = |path: &Path, reason: String| WatchError::Tls(format!("{}: {reason}", path.display()));
+    let open = |path: &Path| fs::File::open(path).map(BufReader::new).map_err(|e| failed(path, e.to_string()));
+
+    let certs = rustls_pemfile::certs(&mut open(&cfg.cert)?)
+        .collect::<std::io::Result<Vec<_>>>()
+        .map_err(|e| failed(&cfg.cert, e.to_string()))?;
+    if certs.is_empty() {
+        return Err(failed(&cfg.cert, "no certificate in the file".to_string()));
+    }
+    let key = rustls_pemfile::private_key(&mut open(&cfg.key)?)
+        .map_err(|e| failed(&cfg.key, e.to_string()))?
+        .ok_or_else(|| failed(&cfg.key, "no private key in the file".to_string()))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| failed(&cfg.cert, e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn handle_tls(stream: TcpStream, acceptor: TlsAcceptor, peer: IpAddr, handler: &Handler) -> Result<()> {
+    let stream = tokio::time::timeout(READ_TIMEOUT, acceptor.accept(stream))
+        .await
+        .map_err(|_| WatchError::Timeout { command: "TLS handshake".to_string(), seconds: READ_TIMEOUT.as_secs() })??;
+    handle_connection(stream, peer, handler).await
+}
+
+async fn handle_connection(mut stream: impl AsyncRead + AsyncWrite + Unpin, peer: IpAddr, handler: &Handler) -> Result<()> {
+    let request = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream, peer))
+        .await
+        .map_err(|_| WatchError::Timeout { command: "reading HTTP request".to_string(), seconds: READ_TIMEOUT.as_secs() })??;
+    let response = match request {
+        Some(request) => handler(request),
+        None => Response::text(400, "bad request"),
+    };
+
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Read one request off the stream, or `None` if it is malformed or too large
+async fn read_request(stream: &mut (impl AsyncRead + Unpin), peer: IpAddr) -> Result<Option<Request>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let header_end = loop {
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buffer.len() > MAX_HEADER_BYTES {
+            return Ok(None);
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let length = match headers.iter().find(|(name, _)| name == "content-length") {
+        Some((_, value)) => match value.parse::<usize>() {
+            Ok(length) if length <= MAX_BODY_BYTES => length,
+            _ => return Ok(None),
+        },
+        None => 0,
+    };
+    let mut body = buffer.split_off(header_end + 4);
+    while body.len() < length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(length);
+
+    Ok(Some(Request {
+        peer,
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
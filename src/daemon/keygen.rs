@@ -0,0 +1,84 @@
+use super::config::{source_files, Config};
+use super::errors::{Result, WatchError};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use log::info;
+use toml_edit::{value, DocumentMut, Table};
+
+/// Generate a dedicated ed25519 deploy key for one repo, point its config entry at it
+/// and return the public key for pasting into the git provider.
+/// The repo is looked up like every other subcommand does; of the files defining
+/// its `[[repos]]` entry, included ones too, the last one is edited.
+pub fn generate_deploy_key(config_paths: &[String], config: &Config, repo_name: &str, key_dir: &Path) -> Result<String> {
+    // Find the repo before touching the filesystem so a typo doesn't leave stray keys around
+    let repo = config.find_repo(repo_name).ok_or_else(|| WatchError::UnknownRepo(repo_name.to_string()))?;
+    let mut found = None;
+    for config_path in source_files(config_paths)? {
+        let config_text = fs::read_to_string(&config_path).map_err(|e| WatchError::Config {
+            path: config_path.display().to_string(),
+            source: e,
+        })?;
+        let mut document: DocumentMut = config_text.parse()?;
+        if find_repo(&mut document, &repo.path).is_some() {
+            found = Some((config_path, document));
+        }
+    }
+    let (config_path, mut document) = found.ok_or_else(|| WatchError::NoRepoTable(repo.name().into_owned()))?;
+
+    fs::create_dir_all(key_dir)?;
+    fs::set_permissions(key_dir, fs::Permissions::from_mode(0o700))?;
+
+    let key_path = key_dir.join(key_file_name(&repo.name()));
+    if key_path.exists() {
+        info!("Reusing existing deploy key {}", key_path.display());
+    } else {
+        info!("Generating ed25519 deploy key {}", key_path.display());
+        let output = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", &format!("rustpdater deploy key for {}", repo.name())])
+            .arg("-f")
+            .arg(&key_path)
+            .output()?;
+        if !output.status.success() {
+            return Err(WatchError::CommandFailed {
+                command: "ssh-keygen".to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            });
+        }
+    }
+    fs::set_permissions(&key_path, fs::Permissions::from_mode(0o600))?;
+
+    if let Some(table) = find_repo(&mut document, &repo.path) {
+        table["ssh_key"] = value(key_path.display().to_string());
+    }
+    fs::write(&config_path, document.to_string())?;
+    info!("Wired ssh_key into {}", config_path.display());
+
+    let public_key = fs::read_to_string(public_key_path(&key_path))?;
+    Ok(public_key.trim().to_string())
+}
+
+/// Find the `[[repos]]` table of the repo at `path`, the key repos are merged on
+fn find_repo<'a>(document: &'a mut DocumentMut, path: &Path) -> Option<&'a mut Table> {
+    document
+        .get_mut("repos")?
+        .as_array_of_tables_mut()?
+        .iter_mut()
+        .find(|table| table.get("path").and_then(|p| p.as_str()).is_some_and(|p| Path::new(p) == path))
+}
+
+/// Turn a repo name into a safe file name
+fn key_file_name(repo_name: &str) -> String {
+    let name: String = repo_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("{}_ed25519", name.trim_matches('_'))
+}
+
+fn public_key_path(key_path: &Path) -> PathBuf {
+    let mut path = key_path.as_os_str().to_owned();
+    path.push(".pub");
+    PathBuf::from(path)
+}
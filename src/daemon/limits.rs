@@ -0,0 +1,59 @@
+use super::repo_config::{HookLimits, RepoCfg};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// Build the command for a hook program, confined by the repo's `hook_limits`:
+/// either inside a transient systemd scope or with plain rlimits
+pub fn command(repo: &RepoCfg, program: &str) -> Command {
+    let Some(limits) = &repo.hook_limits else {
+        return Command::new(program);
+    };
+
+    if limits.systemd_scope {
+        let mut command = Command::new("systemd-run");
+        command.args(["--scope", "--quiet", "--collect"]);
+        if let Some(memory) = limits.memory_mb {
+            command.arg("-p").arg(format!("MemoryMax={memory}M"));
+        }
+        if let Some(processes) = limits.max_processes {
+            command.arg("-p").arg(format!("TasksMax={processes}"));
+        }
+        command.arg("--").arg(program);
+        // A scope has no CPU-time accounting, keep that one as an rlimit
+        apply_rlimits(&mut command, &HookLimits { cpu_seconds: limits.cpu_seconds, ..HookLimits::default() });
+        return command;
+    }
+
+    let mut command = Command::new(program);
+    apply_rlimits(&mut command, limits);
+    command
+}
+
+fn apply_rlimits(command: &mut Command, limits: &HookLimits) {
+    let mut rlimits = Vec::new();
+    if let Some(memory) = limits.memory_mb {
+        rlimits.push((libc::RLIMIT_AS, memory.saturating_mul(1024 * 1024)));
+    }
+    if let Some(seconds) = limits.cpu_seconds {
+        rlimits.push((libc::RLIMIT_CPU, seconds));
+    }
+    if let Some(processes) = limits.max_processes {
+        rlimits.push((libc::RLIMIT_NPROC, processes));
+    }
+    if rlimits.is_empty() {
+        return;
+    }
+
+    // SAFETY: only async-signal-safe syscalls run between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for &(resource, value) in &rlimits {
+                let limit = libc::rlimit { rlim_cur: value, rlim_max: value };
+                if libc::setrlimit(resource, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,242 @@
+use super::config::{Config, DiscoverCfg, MetricsCfg, TlsCfg, WebhookCfg};
+use super::repo_config::{
+    CommitRule, DeployLockCfg, DeployTagsCfg, GithubStatusCfg, HookLimits, HookShell, HookStep, PathHook, PushBackCfg,
+    RepoCfg, RetryCfg, SshOptions, StepCommand, TerraformCfg,
+};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Intervals below this mostly load the remote without making deploys noticeably faster
+const MIN_INTERVAL_SECS: u64 = 10;
+
+/// Keys read outside of a struct: config sections expanded before parsing
+/// and the fields of untagged enums such as `credentials` and hook steps
+const OTHER_KEYS: &[&str] = &[
+    "repos", "templates", "instances", "branches", "defaults", "include", "auth", "pass", "username", "token_env", "run",
+    "timeout", "script", "args",
+];
+
+/// Shell words that aren't programs on the PATH
+const SHELL_BUILTINS: &[&str] = &[
+    ".", ":", "[", "cd", "echo", "eval", "exec", "exit", "export", "false", "printf", "set", "source", "test", "true", "umask",
+];
+
+/// Non-fatal problems in a loaded config: keys nothing reads (`unknown` holds
+/// their paths), very short intervals, hooks whose program can't be found and
+/// repos listed twice
+pub fn lint(config: &Config, unknown: &[String]) -> Vec<String> {
+    let mut warnings: Vec<String> = display_keys(unknown)
+        .iter()
+        .map(|key| match suggest(key) {
+            Some(known) => format!("unknown key `{key}` is ignored (did you mean `{known}`?)"),
+            None => format!("unknown key `{key}` is ignored (typo?)"),
+        })
+        .collect();
+
+    let mut paths: BTreeMap<&Path, usize> = BTreeMap::new();
+    let mut names: BTreeMap<&str, usize> = BTreeMap::new();
+    for repo in &config.repos {
+        *paths.entry(&repo.path).or_default() += 1;
+        if let Some(name) = &repo.name {
+            *names.entry(name).or_default() += 1;
+        }
+
+        if repo.interval < MIN_INTERVAL_SECS {
+            warnings.push(format!(
+                "{} is checked every {}s; under {}s mostly adds load on the remote",
+                repo.name(), repo.interval, MIN_INTERVAL_SECS
+            ));
+        }
+
+        // Scripts come from the checkout after the update, they can't be checked ahead
+        let mut hooks: Vec<StepCommand> = repo
+            .on_change
+            .iter()
+            .chain(&repo.pre_update)
+            .chain(&repo.on_failure)
+            .flat_map(|hook| hook.steps())
+            .filter_map(|step| match step {
+                HookStep::Command(command) | HookStep::Timed { run: command, .. } => Some(command),
+                HookStep::Script { .. } => None,
+            })
+            .collect();
+        hooks.extend(repo.hooks.iter().map(|hook| StepCommand::Shell(hook.run.clone())));
+        if let HookShell::Program(shell) = &repo.shell {
+            if !program_exists(shell, &repo.path) {
+                warnings.push(format!("shell `{}` of {} was not found", shell, repo.name()));
+            }
+        }
+        for cmd in &hooks {
+            let program = match cmd {
+                StepCommand::Shell(cmd) => missing_program(cmd, &repo.path),
+                StepCommand::Argv(argv) => argv.first().map(String::as_str).filter(|program| !program_exists(program, &repo.path)),
+            };
+            if let Some(program) = program {
+                warnings.push(format!("hook `{}` of {}: `{}` was not found", cmd, repo.name(), program));
+            }
+        }
+    }
+
+    for (path, count) in paths {
+        if count > 1 {
+            warnings.push(format!("{} is watched by {} repo entries", path.display(), count));
+        }
+    }
+    for discover in &config.discover {
+        if !discover.root.is_dir() {
+            warnings.push(format!("discover root {} is not a directory", discover.root.display()));
+        }
+    }
+    for (name, count) in names {
+        if count > 1 {
+            warnings.push(format!("{count} repos are named `{name}`; commands taking a repo pick the first"));
+        }
+    }
+    warnings
+}
+
+/// Unknown key paths as written in the file; serde_ignored marks the inside
+/// of an Option with a `?` segment
+pub fn display_keys(unknown: &[String]) -> Vec<String> {
+    unknown.iter().map(|key| key.replace(".?", "")).collect()
+}
+
+/// Unknown keys for the strict-config error, each with a suggestion when
+/// one is close enough
+pub fn describe_keys(unknown: &[String]) -> Vec<String> {
+    display_keys(unknown)
+        .iter()
+        .map(|key| match suggest(key) {
+            Some(known) => format!("`{key}` (did you mean `{known}`?)"),
+            None => format!("`{key}`"),
+        })
+        .collect()
+}
+
+/// The known key closest to the last segment of `key`, if it looks like a typo of it
+fn suggest(key: &str) -> Option<&'static str> {
+    let name = key.rsplit('.').next().unwrap_or(key);
+    let known = [
+        struct_fields::<Config>(),
+        struct_fields::<RepoCfg>(),
+        struct_fields::<MetricsCfg>(),
+        struct_fields::<WebhookCfg>(),
+        struct_fields::<TlsCfg>(),
+        struct_fields::<DiscoverCfg>(),
+        struct_fields::<SshOptions>(),
+        struct_fields::<PathHook>(),
+        struct_fields::<CommitRule>(),
+        struct_fields::<PushBackCfg>(),
+        struct_fields::<HookLimits>(),
+        struct_fields::<DeployLockCfg>(),
+        struct_fields::<RetryCfg>(),
+        struct_fields::<DeployTagsCfg>(),
+        struct_fields::<GithubStatusCfg>(),
+        struct_fields::<TerraformCfg>(),
+        OTHER_KEYS,
+    ];
+    // One edit in short keys, up to a third of the name in longer ones
+    let allowed = (name.chars().count() / 3).clamp(1, 3);
+    known
+        .iter()
+        .flat_map(|fields| fields.iter())
+        .map(|field| (edit_distance(name, field), *field))
+        .filter(|(distance, _)| *distance <= allowed)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field)
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != *cb);
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Field names a struct's derived `Deserialize` asks for, captured by handing
+/// it a deserializer that records them and bails out
+fn struct_fields<T: for<'de> Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldNames;
+
+    #[derive(Debug)]
+    struct Found(&'static [&'static str]);
+
+    impl fmt::Display for Found {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("field names captured")
+        }
+    }
+
+    impl std::error::Error for Found {}
+
+    impl de::Error for Found {
+        fn custom<M: fmt::Display>(_: M) -> Found {
+            Found(&[])
+        }
+    }
+
+    impl<'de> Deserializer<'de> for FieldNames {
+        type Error = Found;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> std::result::Result<V::Value, Found> {
+            Err(Found(&[]))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> std::result::Result<V::Value, Found> {
+            Err(Found(fields))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+            unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    match T::deserialize(FieldNames) {
+        Err(Found(fields)) => fields,
+        Ok(_) => &[],
+    }
+}
+
+/// First word of a shell command when it names a program that doesn't exist
+/// for a hook running in `dir`
+fn missing_program<'a>(cmd: &'a str, dir: &Path) -> Option<&'a str> {
+    // Skip leading VAR=value assignments
+    let program = cmd.split_whitespace().find(|word| !word.contains('='))?;
+    if SHELL_BUILTINS.contains(&program) || program.contains(['$', '`', '(', '"', '\'']) {
+        return None;
+    }
+
+    (!program_exists(program, dir)).then_some(program)
+}
+
+/// Whether `program` can be started from `dir`, where hooks run: relative
+/// paths (and relative `PATH` entries) count from there, not from the daemon's cwd
+fn program_exists(program: &str, dir: &Path) -> bool {
+    if program.contains('/') {
+        is_executable(&dir.join(program))
+    } else {
+        env::var_os("PATH").is_some_and(|path| env::split_paths(&path).any(|entry| is_executable(&dir.join(entry).join(program))))
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata().is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
@@ -0,0 +1,53 @@
+use super::errors::Result;
+use super::git_ops::{self, UpdateEvent};
+use super::repo_config::RepoCfg;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use log::info;
+use serde_json::json;
+
+/// Write the machine-readable "what is deployed" marker for a repo.
+/// Markers inside the checkout are added to git's `info/exclude` so they don't show as local changes.
+pub fn write_deploy_marker(repo: &RepoCfg, marker: &Path, event: &UpdateEvent) -> Result<()> {
+    let path = repo.path.join(marker);
+    let content = json!({
+        "repo": repo.path,
+        "branch": event.branch,
+        "sha": event.new_sha,
+        "previous_sha": event.old_sha,
+        "deployed_at": jiff::Timestamp::now().to_string(),
+        "trigger": event.trigger,
+        "diffstat": event.diffstat,
+    });
+
+    // Write next to the target and rename so readers never see a half-written file
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, format!("{content:#}\n"))?;
+    fs::rename(&tmp, &path)?;
+    info!("Wrote deploy marker {}", path.display());
+
+    if let Ok(relative) = path.strip_prefix(&repo.path) {
+        exclude_from_status(&repo.path, relative)?;
+    }
+    Ok(())
+}
+
+fn exclude_from_status(repo_path: &Path, relative: &Path) -> Result<()> {
+    let exclude = git_ops::git_path(repo_path, "info/exclude")?;
+    let pattern = format!("/{}", relative.display());
+    let existing = fs::read_to_string(&exclude).unwrap_or_default();
+    if existing.lines().any(|line| line == pattern) {
+        return Ok(());
+    }
+
+    if let Some(dir) = exclude.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&exclude)?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{pattern}")?;
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use super::config::{MetricsCfg, MetricsProtocol};
+use super::duration::parse_duration;
+use super::errors::Result;
+use super::stats::Stats;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time;
+use log::{info, warn};
+
+/// Push interval used when `interval` doesn't parse
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Push the check counters and fetch timings to statsd or Graphite every interval
+pub async fn push_loop(cfg: MetricsCfg, stats: Arc<Stats>) {
+    let interval = parse_duration(&cfg.interval).unwrap_or(DEFAULT_INTERVAL);
+    info!("Pushing metrics to {} every {}s", cfg.address, interval.as_secs());
+
+    // Graphite stores absolute values, statsd aggregates the deltas itself
+    let mut totals = [0u64; 3];
+    let mut ticker = time::interval_at(time::Instant::now() + interval, interval);
+    loop {
+        ticker.tick().await;
+        let window = stats.take_push_window();
+        let lines = match cfg.protocol {
+            MetricsProtocol::Statsd => {
+                let mut lines = vec![
+                    format!("{}.checks:{}|c", cfg.prefix, window.checks),
+                    format!("{}.updates:{}|c", cfg.prefix, window.updates),
+                    format!("{}.failures:{}|c", cfg.prefix, window.failures),
+                    format!("{}.repos:{}|g", cfg.prefix, stats.repos_watched()),
+                ];
+                lines.extend(window.fetch_times.iter().map(|t| format!("{}.fetch_time:{}|ms", cfg.prefix, t.as_millis())));
+                lines
+            }
+            MetricsProtocol::Graphite => {
+                totals[0] += window.checks;
+                totals[1] += window.updates;
+                totals[2] += window.failures;
+                let now = jiff::Timestamp::now().as_second();
+                let mut lines = vec![
+                    format!("{}.checks {} {now}", cfg.prefix, totals[0]),
+                    format!("{}.updates {} {now}", cfg.prefix, totals[1]),
+                    format!("{}.failures {} {now}", cfg.prefix, totals[2]),
+                    format!("{}.repos {} {now}", cfg.prefix, stats.repos_watched()),
+                ];
+                if let Some(slowest) = window.fetch_times.iter().max() {
+                    lines.push(format!("{}.fetch_time_max {} {now}", cfg.prefix, slowest.as_millis()));
+                }
+                lines
+            }
+        };
+
+        if let Err(e) = send(&cfg, &lines).await {
+            warn!("Could not push metrics to {}: {}", cfg.address, e);
+        }
+    }
+}
+
+async fn send(cfg: &MetricsCfg, lines: &[String]) -> Result<()> {
+    match cfg.protocol {
+        MetricsProtocol::Statsd => {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(&cfg.address).await?;
+            // One datagram per metric keeps each one well under the MTU
+            for line in lines {
+                socket.send(line.as_bytes()).await?;
+            }
+        }
+        MetricsProtocol::Graphite => {
+            let mut payload = String::new();
+            for line in lines {
+                let _ = writeln!(payload, "{line}");
+            }
+            let mut stream = TcpStream::connect(&cfg.address).await?;
+            stream.write_all(payload.as_bytes()).await?;
+            stream.shutdown().await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use super::duration::parse_duration;
+use super::errors::{Result, WatchError};
+use std::fs;
+use std::path::Path;
+use log::warn;
+use toml_edit::{value, ArrayOfTables, DocumentMut, Table};
+
+/// Existing updater setups that can be translated into a watcher config
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Source {
+    /// git-sync env file (GITSYNC_*/GIT_SYNC_*) or container args (--repo=...)
+    GitSync,
+    /// webhookd hook scripts directory
+    Webhookd,
+    /// crontab with `cd /path && git pull && restart` style entries
+    CronScript,
+}
+
+/// One repo recovered from the old setup
+#[derive(Default)]
+struct Migrated {
+    path: String,
+    url: Option<String>,
+    branch: Option<String>,
+    interval: Option<u64>,
+    on_change: Option<String>,
+    origin: String,
+}
+
+/// Read an existing setup and render an equivalent watcher.toml
+pub fn migrate(source: Source, input: &Path) -> Result<String> {
+    let repos = match source {
+        Source::GitSync => from_git_sync(&read(input)?),
+        Source::CronScript => from_crontab(&read(input)?),
+        Source::Webhookd => from_webhookd(input)?,
+    };
+    Ok(render(&repos))
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| WatchError::Config {
+        path: path.display().to_string(),
+        source: e,
+    })
+}
+
+/// git-sync settings come as `KEY=value` env lines or as `--flag=value` / `--flag value` args
+fn from_git_sync(text: &str) -> Vec<Migrated> {
+    let mut repo = Migrated { origin: "git-sync".to_string(), ..Migrated::default() };
+    let words = shell_words(text);
+    let mut tokens = words.iter().map(|t| t.trim_matches(|c| c == '[' || c == ']' || c == ','));
+    let mut root = None;
+
+    while let Some(token) = tokens.next() {
+        let (key, val) = if let Some((key, val)) = token.split_once('=') {
+            (key.to_string(), val.to_string())
+        } else if token.starts_with("--") {
+            (token.to_string(), tokens.next().unwrap_or_default().to_string())
+        } else {
+            continue;
+        };
+
+        let key = key
+            .trim_start_matches("--")
+            .trim_start_matches("GITSYNC_")
+            .trim_start_matches("GIT_SYNC_")
+            .to_ascii_lowercase()
+            .replace('_', "-");
+        match key.as_str() {
+            "repo" => repo.url = Some(val),
+            "ref" | "branch" => repo.branch = Some(val),
+            "period" => repo.interval = parse_duration(&val).map(|d| d.as_secs().max(1)),
+            "wait" => repo.interval = val.parse::<f64>().ok().map(|s| s.max(1.0) as u64),
+            "root" => root = Some(val),
+            "exechook-command" => repo.on_change = Some(val),
+            _ => {}
+        }
+    }
+
+    repo.path = root.unwrap_or_else(|| "/tmp/git".to_string());
+    if repo.url.is_none() {
+        warn!("No git-sync repo setting found in the input");
+        return Vec::new();
+    }
+    vec![repo]
+}
+
+/// Split text into words on whitespace outside quotes, dropping the quotes
+fn shell_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, c) if c.is_whitespace() => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            (None, c) => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+/// Each crontab entry running `git pull` becomes one repo
+fn from_crontab(text: &str) -> Vec<Migrated> {
+    let mut repos = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (interval, command) = match split_schedule(line) {
+            Some(parts) => parts,
+            None => continue,
+        };
+        if let Some(mut repo) = parse_pull_command(command) {
+            repo.interval = interval;
+            repo.origin = format!("crontab: {line}");
+            repos.push(repo);
+        }
+    }
+    repos
+}
+
+/// webhookd runs one script per hook; scripts that pull a checkout become repos
+fn from_webhookd(dir: &Path) -> Result<Vec<Migrated>> {
+    let mut repos = Vec::new();
+    let mut entries: Vec<_> = fs::read_dir(dir)?.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    entries.sort();
+
+    for script in entries.iter().filter(|p| p.is_file()) {
+        let text = fs::read_to_string(script)?;
+        let commands: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+        if let Some(mut repo) = parse_pull_command(&commands.join(" && ")) {
+            repo.origin = format!("webhookd hook {}", script.display());
+            repos.push(repo);
+        }
+    }
+    Ok(repos)
+}
+
+/// Split a crontab line into its poll interval (if it maps to one) and the command
+fn split_schedule(line: &str) -> Option<(Option<u64>, &str)> {
+    if let Some(rest) = line.strip_prefix('@') {
+        let (name, command) = rest.split_once(char::is_whitespace)?;
+        let interval = match name {
+            "hourly" => Some(3600),
+            "daily" | "midnight" => Some(86400),
+            "weekly" => Some(7 * 86400),
+            _ => None,
+        };
+        return Some((interval, command.trim()));
+    }
+
+    let mut rest = line;
+    let mut fields = Vec::new();
+    for _ in 0..5 {
+        let (field, tail) = rest.split_once(char::is_whitespace)?;
+        fields.push(field);
+        rest = tail.trim_start();
+    }
+    // Environment assignments like `PATH=/usr/bin` have no schedule fields
+    if fields[0].contains('=') {
+        return None;
+    }
+
+    let day_fields_wildcard = fields[2..].iter().all(|f| *f == "*");
+    let minute_fixed = fields[0].parse::<u64>().is_ok();
+    let interval = match (fields[0], fields[1]) {
+        _ if !day_fields_wildcard => None,
+        ("*", "*") => Some(60),
+        (minute, "*") if minute.starts_with("*/") => step(&minute[2..]).map(|m| m * 60),
+        (_, "*") if minute_fixed => Some(3600),
+        (_, hour) if minute_fixed && hour.starts_with("*/") => step(&hour[2..]).map(|h| h * 3600),
+        _ => None,
+    };
+    Some((interval, rest))
+}
+
+/// The N of a `*/N` field; cron rejects a step of 0 like a non-numeric one
+fn step(field: &str) -> Option<u64> {
+    field.parse::<u64>().ok().filter(|step| *step > 0)
+}
+
+/// Recognise `cd <path> && git pull [remote] [branch] && <hook...>` and `git -C <path> pull`
+fn parse_pull_command(command: &str) -> Option<Migrated> {
+    let steps: Vec<&str> = command.split("&&").flat_map(|s| s.split(';')).map(str::trim).collect();
+    let mut path = None;
+    let mut branch = None;
+    let mut hook = Vec::new();
+    let mut pulled = false;
+
+    for step in steps.into_iter().filter(|s| !s.is_empty()) {
+        let words: Vec<&str> = step.split_whitespace().collect();
+        if !pulled && words.first() == Some(&"cd") && words.len() > 1 {
+            path = Some(words[1].to_string());
+        } else if !pulled && words.first() == Some(&"git") && words.contains(&"pull") {
+            if let Some(pos) = words.iter().position(|w| *w == "-C") {
+                path = words.get(pos + 1).map(|p| p.to_string());
+            }
+            let args: Vec<&&str> = words
+                .iter()
+                .skip_while(|w| **w != "pull")
+                .skip(1)
+                .filter(|w| !w.starts_with('-') && !w.contains('>'))
+                .collect();
+            branch = args.get(1).map(|b| b.to_string());
+            pulled = true;
+        } else if pulled {
+            hook.push(step);
+        }
+    }
+
+    if !pulled {
+        return None;
+    }
+    Some(Migrated {
+        path: path?,
+        branch,
+        on_change: (!hook.is_empty()).then(|| hook.join(" && ")),
+        ..Migrated::default()
+    })
+}
+
+fn render(repos: &[Migrated]) -> String {
+    let mut document = DocumentMut::new();
+    let mut tables = ArrayOfTables::new();
+
+    for repo in repos {
+        let mut table = Table::new();
+        table.decor_mut().set_prefix(format!("\n# migrated from {}\n", repo.origin));
+        table["path"] = value(repo.path.as_str());
+        if let Some(url) = &repo.url {
+            table["url"] = value(url.as_str());
+        }
+        if let Some(branch) = &repo.branch {
+            table["branch"] = value(branch.as_str());
+        }
+        if let Some(interval) = repo.interval {
+            table["interval"] = value(interval as i64);
+        }
+        if let Some(on_change) = &repo.on_change {
+            table["on_change"] = value(on_change.as_str());
+        }
+        tables.push(table);
+    }
+
+    document["repos"] = toml_edit::Item::ArrayOfTables(tables);
+    document.to_string().trim_start().to_string()
+}
@@ -3,3 +3,31 @@ pub mod watcher;
 pub mod repo_config;
 pub mod config;
 pub mod git_ops;
+pub mod duration;
+pub mod http;
+pub mod sidecar;
+pub mod terraform;
+pub mod keygen;
+pub mod migrate;
+pub mod secrets;
+pub mod stats;
+pub mod marker;
+pub mod glob;
+pub mod commit_rules;
+pub mod priority;
+pub mod limits;
+pub mod credentials;
+pub mod drift;
+pub mod github;
+pub mod deploy_lock;
+pub mod metrics;
+pub mod heartbeat;
+pub mod status;
+pub mod setup;
+pub mod lint;
+pub mod deploy_tags;
+pub mod hooks;
+pub mod webhook;
+pub mod allowlist;
+pub mod validate;
+pub mod control;
@@ -0,0 +1,55 @@
+use super::repo_config::RepoCfg;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use log::warn;
+
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+/// Lower the CPU and IO priority of a hook process per the repo's
+/// `hook_nice`/`hook_ionice`, applied in the child right before exec
+pub fn apply(command: &mut Command, repo: &RepoCfg) {
+    let nice = repo.hook_nice;
+    let ionice = repo.hook_ionice.as_deref().and_then(|value| {
+        let parsed = parse_ionice(value);
+        if parsed.is_none() {
+            warn!("Ignoring invalid hook_ionice '{}' for {}", value, repo.name());
+        }
+        parsed
+    });
+    if nice.is_none() && ionice.is_none() {
+        return;
+    }
+
+    // SAFETY: only async-signal-safe syscalls run between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            if let Some(ioprio) = ionice {
+                if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Parse "idle", "best-effort[:0-7]" or "realtime[:0-7]" into an ioprio value
+fn parse_ionice(value: &str) -> Option<libc::c_int> {
+    let (class, level) = match value.split_once(':') {
+        Some((class, level)) => (class, level.parse::<libc::c_int>().ok().filter(|l| (0..=7).contains(l))?),
+        None => (value, 4),
+    };
+    let class = match class {
+        "realtime" => 1,
+        "best-effort" => 2,
+        "idle" => return Some(3 << IOPRIO_CLASS_SHIFT),
+        _ => return None,
+    };
+    Some((class << IOPRIO_CLASS_SHIFT) | level)
+}
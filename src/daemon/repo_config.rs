@@ -1,20 +1,545 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::PathBuf;
 use serde::Deserialize;
 
 
-fn default_branch() -> String { "master".to_string() }
 fn default_interval() -> u64 { 300 /*5 minutes in seconds */ }
+fn default_true() -> bool { true }
+fn default_terraform_binary() -> String { "terraform".to_string() }
+fn default_lock_slots() -> u32 { 1 }
+fn default_lock_lease() -> String { "15m".to_string() }
+fn default_github_token_env() -> String { "GITHUB_TOKEN".to_string() }
+fn default_github_api() -> String { "https://api.github.com".to_string() }
+fn default_max_retries() -> u32 { 3 }
+fn default_retry_base_delay() -> String { "2s".to_string() }
+fn default_retry_max_delay() -> String { "30s".to_string() }
+fn default_tag_pattern() -> String { "deploy/{timestamp}".to_string() }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct RepoCfg {
     /// Local checkout path
     pub path: PathBuf,
-    /// Branch to watch (default main)
-    #[serde(default = "default_branch")]
-    pub branch: String,
+    /// Short name used in logs, CLI output and `status` instead of the path (optional)
+    pub name: Option<String>,
+    /// Remote to clone from when the path does not exist yet (optional)
+    pub url: Option<String>,
+    /// Clone whose object store this checkout shares as a git worktree (optional)
+    pub shared_clone: Option<PathBuf>,
+    /// Keep a working checkout (default) or a bare mirror of every ref
+    #[serde(default)]
+    pub mode: Mode,
+    /// How the checkout follows the fetched commit: hard reset (default) or fast-forward only
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Branch to watch; the remote's default branch is detected at startup when omitted
+    pub branch: Option<String>,
+    /// Switch to the remote's default branch when the watched one disappears upstream
+    #[serde(default)]
+    pub follow_default: bool,
+    /// Track the newest remote ref matching this pattern instead of `branch` (optional)
+    pub ref_glob: Option<String>,
+    /// How "newest" is decided for `ref_glob`
+    #[serde(default)]
+    pub ref_sort: RefSort,
+    /// Partial clone filter such as "blob:none" or "tree:0" (optional)
+    pub filter: Option<String>,
+    /// Which tags to bring down with each fetch
+    #[serde(default)]
+    pub fetch_tags: FetchTags,
+    /// Delete local tags that no longer exist on the remote
+    #[serde(default)]
+    pub prune_tags: bool,
     /// Poll interval in seconds
     #[serde(default = "default_interval")]
     pub interval: u64,
-    /// Command to run after update (optional)
-    pub on_change: Option<String>,
+    /// Check right after startup (default) instead of one interval later
+    #[serde(default = "default_true")]
+    pub check_on_start: bool,
+    /// Fixed wait before the first check, e.g. "5m" to let services settle after a reboot (optional)
+    pub startup_delay: Option<String>,
+    /// Order in which waiting checks get a slot when `max_concurrent_checks` is reached
+    #[serde(default)]
+    pub priority: Priority,
+    /// Seconds a git command talking to the remote may take before git and ssh are killed (optional)
+    pub fetch_timeout_secs: Option<u64>,
+    /// Retry failed fetches within a check, with exponential backoff (optional)
+    pub retry: Option<RetryCfg>,
+    /// Stop checking after this many failed checks in a row (optional)
+    pub max_consecutive_failures: Option<u32>,
+    /// Retry a repo paused by `max_consecutive_failures` once after this long, e.g. "1h" (optional)
+    pub failure_cooldown: Option<String>,
+    /// Wait after moving the checkout and fetch again before running hooks, e.g. "10s" (optional)
+    pub settle: Option<String>,
+    /// Command, or steps, run before the checkout moves; exiting non-zero skips the update this check (optional)
+    pub pre_update: Option<OnChange>,
+    /// Command, or steps run in order, after update (optional)
+    pub on_change: Option<OnChange>,
+    /// Shell string hooks run with: `sh` (default), another shell, or `false` to exec them directly
+    #[serde(default)]
+    pub shell: HookShell,
+    /// Seconds a hook step may run when it sets no `timeout` of its own (optional)
+    pub hook_timeout_secs: Option<u64>,
+    /// Move the checkout back to the previous commit when `on_change` fails
+    #[serde(default)]
+    pub rollback_on_hook_failure: bool,
+    /// Command, or steps, run when a check, its update or its hooks fail (optional)
+    pub on_failure: Option<OnChange>,
+    /// Keep a `refs/rustpdater/backup/<timestamp>` ref for every replaced commit
+    #[serde(default)]
+    pub backup_refs: bool,
+    /// Stash uncommitted changes to tracked files before an update and reapply them after
+    #[serde(default)]
+    pub autostash: bool,
+    /// Revert local modifications even when the remote hasn't changed
+    #[serde(default)]
+    pub reconcile: bool,
+    /// Where reverted drift is kept: "stash" or a backup directory (optional)
+    pub drift_backup: Option<String>,
+    /// Watch the working tree for changes made outside of an update
+    #[serde(default)]
+    pub watch_files: bool,
+    /// What to do when `watch_files` sees local changes
+    #[serde(default)]
+    pub drift_policy: DriftPolicy,
+    /// Extra environment variables for the hooks
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Commands run only when the update touched matching paths
+    #[serde(default)]
+    pub hooks: Vec<PathHook>,
+    /// Conventional-commit rules deciding how an update is handled
+    #[serde(default)]
+    pub commit_rules: Vec<CommitRule>,
+    /// Niceness (-20..19) for hook processes (optional)
+    pub hook_nice: Option<i32>,
+    /// IO scheduling class for hook processes: "idle", "best-effort:N" or "realtime:N" (optional)
+    pub hook_ionice: Option<String>,
+    /// Memory, CPU time and process limits for hook processes (optional)
+    pub hook_limits: Option<HookLimits>,
+    /// Terraform/OpenTofu plan-and-apply run after update (optional)
+    pub terraform: Option<TerraformCfg>,
+    /// Coordinate updates with other hosts through lock refs on the remote (optional)
+    pub deploy_lock: Option<DeployLockCfg>,
+    /// Report each deploy back to GitHub as a commit or deployment status (optional)
+    pub github_status: Option<GithubStatusCfg>,
+    /// Tag every deployed commit in the local repo (optional)
+    pub deploy_tags: Option<DeployTagsCfg>,
+    /// Commit files generated by the hooks and push them to a branch (optional)
+    pub push_back: Option<PushBackCfg>,
+    /// Address family for connections to the remote, overriding the global `ip_family` (optional)
+    pub ip_family: Option<IpFamily>,
+    /// Port, user and host alias for SSH remotes (optional)
+    pub ssh_options: Option<SshOptions>,
+    /// Share one SSH connection per host between fetches, overriding the global `ssh_multiplex` (optional)
+    pub ssh_multiplex: Option<bool>,
+    /// Dedicated SSH private key for this repo (optional)
+    pub ssh_key: Option<PathBuf>,
+    /// File holding the passphrase of `ssh_key` (optional)
+    pub ssh_key_passphrase_file: Option<PathBuf>,
+    /// Where HTTPS tokens come from, overriding the global `credentials` (optional)
+    #[serde(alias = "auth")]
+    pub credentials: Option<Credentials>,
+    /// Program asked for credentials and key passphrases, as GIT_ASKPASS/SSH_ASKPASS (optional)
+    pub askpass: Option<PathBuf>,
+    /// JSON file describing the deployed commit, relative to the repo (optional)
+    pub deploy_marker: Option<PathBuf>,
+    /// Dead-man's-switch URL pinged after every check, with `/fail` appended on errors (optional)
+    pub heartbeat: Option<String>,
+    /// Secret push webhooks for this repo must be signed with (optional)
+    pub webhook_secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Working tree tracking one branch
+    #[default]
+    Checkout,
+    /// `--mirror` style bare clone with all refs, pruned, no checkout
+    Mirror,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Hard reset to the fetched commit, following force-pushes and discarding local commits
+    #[default]
+    Reset,
+    /// Only move forward; a rewritten branch is reported and left alone
+    FfOnly,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DriftPolicy {
+    /// Log the changed files
+    #[default]
+    Warn,
+    /// Put the files back to HEAD, keeping them per `drift_backup`
+    Revert,
+    /// Run `on_change` again, e.g. to regenerate what was edited
+    Rehook,
+}
+
+/// Ordered from least to most urgent
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchTags {
+    /// Only the watched branch
+    #[default]
+    None,
+    /// Tags pointing into the fetched history
+    Follow,
+    /// Every tag on the remote
+    All,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    /// Let the resolver and git decide
+    #[default]
+    Auto,
+    /// IPv4 only
+    V4,
+    /// IPv6 only
+    V6,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SshOptions {
+    /// Port to connect to; a port in an `ssh://` URL still takes precedence
+    pub port: Option<u16>,
+    /// Login user, overriding the one in the URL
+    pub user: Option<String>,
+    /// Host name to connect to instead of the URL's, e.g. a `Host` entry of ~/.ssh/config
+    pub host_alias: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Credentials {
+    /// Token from a CLI that is already logged in, e.g. "gh"
+    Cli(CredentialCli),
+    /// Token read with `pass show <entry>`; `{host}` and `{repo}` are filled from the remote URL
+    Pass {
+        pass: String,
+        username: Option<String>,
+    },
+    /// Token read from an environment variable, e.g. one set by a secret manager
+    Env {
+        token_env: String,
+        username: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialCli {
+    /// `gh auth token` of the GitHub CLI
+    Gh,
+}
+
+impl Credentials {
+    /// Username sent along with the token
+    pub fn username(&self) -> &str {
+        match self {
+            Credentials::Cli(_) => "x-access-token",
+            Credentials::Pass { username, .. } | Credentials::Env { username, .. } => {
+                username.as_deref().unwrap_or("x-access-token")
+            }
+        }
+    }
+}
+
+/// `on_change`: one shell command, a single step such as a script, or a chain
+/// of steps stopping at the first failure
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum OnChange {
+    Shell(String),
+    Steps(Vec<HookStep>),
+    Step(HookStep),
+}
+
+impl OnChange {
+    pub fn steps(&self) -> Vec<HookStep> {
+        match self {
+            OnChange::Shell(cmd) => vec![HookStep::Command(StepCommand::Shell(cmd.clone()))],
+            OnChange::Steps(steps) => steps.clone(),
+            OnChange::Step(step) => vec![step.clone()],
+        }
+    }
+}
+
+/// `shell`: `true` runs string hooks with `sh -c`, a program name such as
+/// "bash" with `<shell> -c`, and `false` splits them on whitespace and
+/// starts the first word directly
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum HookShell {
+    Enabled(bool),
+    Program(String),
+}
+
+impl Default for HookShell {
+    fn default() -> HookShell {
+        HookShell::Enabled(true)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum HookStep {
+    Command(StepCommand),
+    /// `{ run = ..., timeout = "5m" }`
+    Timed { run: StepCommand, timeout: Option<String> },
+    /// `{ script = "deploy/update.sh", args = [...] }`, relative to the checkout
+    Script {
+        script: PathBuf,
+        #[serde(default)]
+        args: Vec<String>,
+        timeout: Option<String>,
+    },
+}
+
+impl HookStep {
+    pub fn timeout(&self) -> Option<&str> {
+        match self {
+            HookStep::Command(_) => None,
+            HookStep::Timed { timeout, .. } | HookStep::Script { timeout, .. } => timeout.as_deref(),
+        }
+    }
+}
+
+impl fmt::Display for HookStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookStep::Command(command) | HookStep::Timed { run: command, .. } => command.fmt(f),
+            HookStep::Script { script, args, .. } => {
+                write!(f, "{}", script.display())?;
+                args.iter().try_for_each(|arg| write!(f, " {arg}"))
+            }
+        }
+    }
+}
+
+/// A shell snippet, or a program and its arguments run without a shell
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum StepCommand {
+    Shell(String),
+    Argv(Vec<String>),
+}
+
+impl fmt::Display for StepCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepCommand::Shell(cmd) => f.write_str(cmd),
+            StepCommand::Argv(argv) => f.write_str(&argv.join(" ")),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PathHook {
+    /// Globs relative to the repo root, e.g. "api/**"
+    pub paths: Vec<String>,
+    /// Command to run when any changed file matches
+    pub run: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CommitRule {
+    /// Commit labels to match: "type(scope)", "type" or "BREAKING CHANGE" (globs allowed)
+    pub types: Vec<String>,
+    pub action: CommitAction,
+}
+
+/// What to do with an update, ordered from most to least permissive
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitAction {
+    /// Update as usual
+    Deploy,
+    /// Leave the checkout alone until `rustpdater approve` is run
+    Hold,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RefSort {
+    /// Highest version-sorted ref name
+    #[default]
+    Version,
+    /// Most recent commit date
+    Date,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PushBackCfg {
+    /// Branch receiving the generated commit
+    pub branch: String,
+    /// Globs of generated files to include, relative to the repo
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HookLimits {
+    /// Address space (or cgroup memory with `systemd_scope`) in MiB
+    pub memory_mb: Option<u64>,
+    /// CPU time in seconds before the process is killed
+    pub cpu_seconds: Option<u64>,
+    /// Maximum number of processes (or cgroup tasks)
+    pub max_processes: Option<u64>,
+    /// Run hooks in a transient `systemd-run --scope` instead of using rlimits
+    #[serde(default)]
+    pub systemd_scope: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeployLockCfg {
+    /// Lock name shared by every host deploying together (default the branch)
+    pub name: Option<String>,
+    /// How many hosts may update at the same time
+    #[serde(default = "default_lock_slots")]
+    pub slots: u32,
+    /// Age after which a slot left by a crashed host is taken over, e.g. "15m"
+    #[serde(default = "default_lock_lease")]
+    pub lease: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RetryCfg {
+    /// Retries after the first failed fetch
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each following one
+    #[serde(default = "default_retry_base_delay")]
+    pub base_delay: String,
+    /// Longest wait between two retries
+    #[serde(default = "default_retry_max_delay")]
+    pub max_delay: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeployTagsCfg {
+    /// Tag name with `{timestamp}`, `{sha}` and `{branch}` placeholders
+    #[serde(default = "default_tag_pattern")]
+    pub pattern: String,
+    /// Number of deploy tags to keep, oldest pruned first (optional, keeps all)
+    pub keep: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GithubStatusCfg {
+    /// "owner/name", derived from a github.com remote when omitted
+    pub repo: Option<String>,
+    /// Environment variable holding the API token
+    #[serde(default = "default_github_token_env")]
+    pub token_env: String,
+    /// Create a deployment in this environment instead of a plain commit status (optional)
+    pub environment: Option<String>,
+    /// API base, for GitHub Enterprise
+    #[serde(default = "default_github_api")]
+    pub api_url: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TerraformCfg {
+    /// Root module directory, relative to the repo (default repo root)
+    pub dir: Option<PathBuf>,
+    /// Binary to invoke, e.g. "terraform" or "tofu"
+    #[serde(default = "default_terraform_binary")]
+    pub binary: String,
+    /// Apply the plan right away instead of leaving it for manual approval
+    #[serde(default)]
+    pub auto_apply: bool,
+}
+
+impl RepoCfg {
+    /// Branch to fetch: the configured or detected one, otherwise the remote's HEAD
+    pub fn branch(&self) -> &str {
+        self.branch.as_deref().unwrap_or("HEAD")
+    }
+
+    /// What logs and messages call the repo: its `name`, otherwise its path
+    pub fn name(&self) -> Cow<'_, str> {
+        match &self.name {
+            Some(name) => Cow::Borrowed(name),
+            None => self.path.to_string_lossy(),
+        }
+    }
+
+    /// Build a repo entry with every optional field at its default
+    pub fn new(path: PathBuf) -> RepoCfg {
+        RepoCfg {
+            path,
+            name: None,
+            url: None,
+            shared_clone: None,
+            mode: Mode::default(),
+            strategy: Strategy::default(),
+            branch: None,
+            follow_default: false,
+            ref_glob: None,
+            ref_sort: RefSort::default(),
+            filter: None,
+            fetch_tags: FetchTags::default(),
+            prune_tags: false,
+            interval: default_interval(),
+            check_on_start: true,
+            startup_delay: None,
+            priority: Priority::default(),
+            fetch_timeout_secs: None,
+            retry: None,
+            max_consecutive_failures: None,
+            failure_cooldown: None,
+            settle: None,
+            pre_update: None,
+            on_change: None,
+            shell: HookShell::default(),
+            hook_timeout_secs: None,
+            rollback_on_hook_failure: false,
+            on_failure: None,
+            backup_refs: false,
+            autostash: false,
+            reconcile: false,
+            drift_backup: None,
+            watch_files: false,
+            drift_policy: DriftPolicy::default(),
+            env: BTreeMap::new(),
+            hooks: Vec::new(),
+            commit_rules: Vec::new(),
+            hook_nice: None,
+            hook_ionice: None,
+            hook_limits: None,
+            terraform: None,
+            deploy_lock: None,
+            github_status: None,
+            deploy_tags: None,
+            push_back: None,
+            ip_family: None,
+            ssh_options: None,
+            ssh_multiplex: None,
+            ssh_key: None,
+            ssh_key_passphrase_file: None,
+            credentials: None,
+            askpass: None,
+            deploy_marker: None,
+            heartbeat: None,
+            webhook_secret: None,
+        }
+    }
 }
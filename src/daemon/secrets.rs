@@ -0,0 +1,137 @@
+use super::errors::{Result, WatchError};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use log::info;
+use toml::Value;
+
+/// Prefix marking an age-encrypted config value
+const ENCRYPTED_PREFIX: &str = "enc:";
+const ARMOR_BEGIN: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+const ARMOR_END: &str = "-----END AGE ENCRYPTED FILE-----";
+const DEFAULT_KEY_FILE: &str = "/etc/rustpdater/age.key";
+/// The identity itself, for containers that get secrets as variables rather than files
+const KEY_ENV: &str = "RUSTPDATER_AGE_KEY";
+const KEY_FILE_ENV: &str = "RUSTPDATER_AGE_KEY_FILE";
+
+/// Replace every `enc:...` string in the config with its decrypted value.
+/// The payload is either an armored age file or the base64 of a binary one
+/// (`age -r <recipient> | base64 -w0`). The identity comes from
+/// `RUSTPDATER_AGE_KEY`, then the file named by `RUSTPDATER_AGE_KEY_FILE`,
+/// falling back to /etc/rustpdater/age.key.
+pub fn decrypt_values(value: &mut Value) -> Result<()> {
+    let mut count = 0;
+    walk(value, &mut None, &mut count)?;
+    if count > 0 {
+        info!("Decrypted {} secret value(s) in the config", count);
+    }
+    Ok(())
+}
+
+/// Decrypt the values under `value`, finding the identity on the first encrypted one
+fn walk(value: &mut Value, key: &mut Option<KeyFile>, count: &mut usize) -> Result<()> {
+    match value {
+        Value::String(text) => {
+            if let Some(payload) = text.strip_prefix(ENCRYPTED_PREFIX) {
+                let key = match key {
+                    Some(key) => key,
+                    None => key.insert(KeyFile::find()?),
+                };
+                *text = decrypt(payload, key)?;
+                *count += 1;
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                walk(item, key, count)?;
+            }
+        }
+        Value::Table(table) => {
+            for (_, item) in table.iter_mut() {
+                walk(item, key, count)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The age identity file handed to `age -i`. One taken from `RUSTPDATER_AGE_KEY`
+/// is written to a private temp file, removed again once loading is done.
+struct KeyFile {
+    path: PathBuf,
+    temporary: bool,
+}
+
+impl KeyFile {
+    fn find() -> Result<KeyFile> {
+        if let Some(key) = env::var(KEY_ENV).ok().filter(|key| !key.trim().is_empty()) {
+            let path = env::temp_dir().join(format!("rustpdater-age-{}.key", process::id()));
+            // Left over from an earlier process with the same pid; create_new refuses to follow anything put here
+            let _ = fs::remove_file(&path);
+            let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path)?;
+            let key_file = KeyFile { path, temporary: true };
+            writeln!(file, "{}", key.trim())?;
+            return Ok(key_file);
+        }
+
+        let path = env::var_os(KEY_FILE_ENV).map(PathBuf::from).unwrap_or_else(|| PathBuf::from(DEFAULT_KEY_FILE));
+        if !path.exists() {
+            return Err(WatchError::Decrypt(format!(
+                "config holds encrypted values but neither {} is set nor the age identity {} exists",
+                KEY_ENV,
+                path.display()
+            )));
+        }
+        Ok(KeyFile { path, temporary: false })
+    }
+}
+
+impl Drop for KeyFile {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn decrypt(payload: &str, key: &KeyFile) -> Result<String> {
+    let mut child = Command::new("age")
+        .arg("--decrypt")
+        .arg("-i")
+        .arg(&key.path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(armor(payload).as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(WatchError::Decrypt(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(String::from_utf8(output.stdout)?.trim_end_matches('\n').to_string())
+}
+
+/// Wrap a base64 payload in age's ASCII armor, which requires 64 column lines
+fn armor(payload: &str) -> String {
+    let payload = payload.trim();
+    if payload.starts_with(ARMOR_BEGIN) {
+        return format!("{payload}\n");
+    }
+
+    let base64: String = payload.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut armored = format!("{ARMOR_BEGIN}\n");
+    for line in base64.as_bytes().chunks(64) {
+        armored.push_str(&String::from_utf8_lossy(line));
+        armored.push('\n');
+    }
+    armored.push_str(ARMOR_END);
+    armored.push('\n');
+    armored
+}
@@ -0,0 +1,192 @@
+use super::config::Config;
+use super::errors::{Result, WatchError};
+use super::git_ops;
+use super::repo_config::RepoCfg;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
+
+/// Where the systemd unit offered at the end of the wizard is written
+const UNIT_PATH: &str = "/etc/systemd/system/rustpdater.service";
+
+/// Walk through adding repos on the terminal, check that each remote answers
+/// with the credentials the daemon would use, append them to `config_path` and
+/// offer to install the systemd unit
+pub fn run(config_path: &Path) -> Result<()> {
+    println!("Adding repos to {}. Leave the path empty when done.\n", config_path.display());
+
+    let mut document: DocumentMut = match fs::read_to_string(config_path) {
+        Ok(text) => text.parse()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => DocumentMut::new(),
+        Err(e) => return Err(WatchError::Config { path: config_path.display().to_string(), source: e }),
+    };
+
+    let mut added = Vec::new();
+    loop {
+        let path = ask("Repository path", "")?;
+        if path.is_empty() {
+            break;
+        }
+        if let Some(table) = ask_repo(config_path, PathBuf::from(&path))? {
+            added.push(table);
+        }
+        println!();
+    }
+
+    if added.is_empty() {
+        println!("No repos added, {} left untouched", config_path.display());
+        return Ok(());
+    }
+
+    if !document.contains_key("repos") {
+        document.insert("repos", Item::ArrayOfTables(ArrayOfTables::new()));
+    }
+    let repos = document["repos"].as_array_of_tables_mut().ok_or_else(|| WatchError::CommandFailed {
+        command: "setup".to_string(),
+        stderr: format!("`repos` in {} is not an array of tables", config_path.display()),
+    })?;
+    for table in added {
+        repos.push(table);
+    }
+    if let Some(dir) = config_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(config_path, document.to_string())?;
+    println!("Wrote {}", config_path.display());
+
+    if confirm(&format!("Install and start the systemd unit {UNIT_PATH}?"))? {
+        install_unit(config_path)?;
+    } else {
+        println!("Start it with: rustpdater -c {}", config_path.display());
+    }
+    Ok(())
+}
+
+/// Ask for the remaining settings of one repo; None when the user gives up on it
+fn ask_repo(config_path: &Path, path: PathBuf) -> Result<Option<Table>> {
+    let existing_url = path.join(".git").exists().then(|| git_ops::get_remote_url(&path).ok()).flatten();
+    let url = match &existing_url {
+        Some(url) => {
+            println!("  Existing checkout of {url}");
+            url.clone()
+        }
+        None => ask("  Remote URL", "")?,
+    };
+    if url.is_empty() {
+        println!("  No remote URL, skipping {}", path.display());
+        return Ok(None);
+    }
+
+    // Probe with what the daemon would use: the config's defaults and global credentials too
+    let mut probe = toml::Table::new();
+    probe.insert("path".to_string(), toml::Value::String(path.display().to_string()));
+    probe.insert("url".to_string(), toml::Value::String(url.clone()));
+    let mut repo = match Config::preview_repo(config_path, &path, probe) {
+        Ok(repo) => repo,
+        Err(e) => {
+            println!("  Could not read {} ({e}), checking with this repo's settings only", config_path.display());
+            RepoCfg { url: Some(url.clone()), ..RepoCfg::new(path.clone()) }
+        }
+    };
+
+    // ls-remote runs in the repo's directory, which may not exist yet
+    if !path.is_dir() {
+        repo.path = std::env::temp_dir();
+    }
+    let detected = match git_ops::detect_default_branch(&repo) {
+        Ok(branch) => {
+            println!("  Authenticated to {url}, default branch '{branch}'");
+            branch
+        }
+        Err(e) => {
+            println!("  Could not reach {url}: {e}");
+            if !confirm("  Add it anyway?")? {
+                return Ok(None);
+            }
+            "main".to_string()
+        }
+    };
+
+    let branch = ask("  Branch", &detected)?;
+    let interval = loop {
+        let answer = ask("  Check interval in seconds", "60")?;
+        match answer.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => break seconds,
+            _ => println!("  '{answer}' is not a number of seconds"),
+        }
+    };
+    let on_change = ask("  Command to run after an update (on_change)", "")?;
+
+    let mut table = Table::new();
+    table["path"] = value(path.display().to_string());
+    if existing_url.is_none() {
+        table["url"] = value(url);
+    }
+    table["branch"] = value(branch);
+    table["interval"] = value(interval as i64);
+    if !on_change.is_empty() {
+        table["on_change"] = value(on_change);
+    }
+    Ok(Some(table))
+}
+
+/// Write a unit running this binary with `config_path`, then enable and start it
+fn install_unit(config_path: &Path) -> Result<()> {
+    let binary = std::env::current_exe()?;
+    let config_path = fs::canonicalize(config_path)?;
+    let unit = format!(
+        "[Unit]\n\
+         Description=Rustpdater – Git auto‑updater\n\
+         After=network-online.target\n\
+         Wants=network-online.target\n\n\
+         [Service]\n\
+         ExecStart={} --config-file {}\n\
+         ExecReload=/bin/kill -HUP $MAINPID\n\
+         KillMode=mixed\n\
+         Restart=always\n\
+         RestartSec=5\n\n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        binary.display(),
+        config_path.display()
+    );
+    fs::write(UNIT_PATH, unit)?;
+    println!("Wrote {UNIT_PATH}");
+
+    for args in [&["daemon-reload"][..], &["enable", "--now", "rustpdater"]] {
+        let output = Command::new("systemctl").args(args).output()?;
+        if !output.status.success() {
+            return Err(WatchError::CommandFailed {
+                command: format!("systemctl {}", args.join(" ")),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+    }
+    println!("rustpdater is running; follow it with: journalctl -u rustpdater -f");
+    Ok(())
+}
+
+/// Prompt on stdout and read one trimmed line, falling back to `default` when empty
+fn ask(question: &str, default: &str) -> Result<String> {
+    if default.is_empty() {
+        print!("{question}: ");
+    } else {
+        print!("{question} [{default}]: ");
+    }
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        // End of input behaves like an empty answer, which ends the wizard
+        println!();
+    }
+    let answer = line.trim();
+    Ok(if answer.is_empty() { default.to_string() } else { answer.to_string() })
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    let answer = ask(&format!("{question} [y/N]"), "")?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
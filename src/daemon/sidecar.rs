@@ -0,0 +1,57 @@
+use super::errors::Result;
+use super::http::{self, Handler, Response};
+use super::repo_config::RepoCfg;
+use super::watcher::{self, LoopControl};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use log::{error, info};
+
+/// Settings for running next to an application container in a pod
+pub struct SidecarOptions {
+    /// Sentinel written once the first sync succeeded, relative paths inside the git dir
+    pub ready_file: PathBuf,
+    /// Address serving `/livez` and `/readyz`
+    pub probe_listen: SocketAddr,
+}
+
+/// Sync a single repo until SIGTERM/SIGINT, exposing kubelet probes meanwhile
+pub async fn run(repo: RepoCfg, options: SidecarOptions) -> Result<()> {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let control = LoopControl {
+        ready_file: Some(options.ready_file),
+        shutdown: Some(shutdown_rx),
+        ..LoopControl::default()
+    };
+
+    // Liveness is answered as long as the runtime is alive, readiness after the first sync
+    let ready = control.ready.clone();
+    let handler: Handler = Arc::new(move |request| match request.path.as_str() {
+        _ if request.method != "GET" && request.method != "HEAD" => Response::text(405, "method not allowed"),
+        "/livez" | "/healthz" => Response::text(200, "ok"),
+        "/readyz" if ready.load(Ordering::SeqCst) => Response::text(200, "ready"),
+        "/readyz" => Response::text(503, "not ready"),
+        _ => Response::text(404, "not found"),
+    });
+    let probe_listen = options.probe_listen;
+    tokio::spawn(async move {
+        if let Err(e) = http::serve(probe_listen, None, handler).await {
+            error!("Probe listener on {} failed: {}", probe_listen, e);
+        }
+    });
+
+    // Translate termination signals into a shutdown request for the poll loop
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM, finishing current sync"),
+            _ = tokio::signal::ctrl_c() => info!("Received SIGINT, finishing current sync"),
+        }
+        let _ = shutdown_tx.send(true);
+    });
+
+    watcher::watch_single_repo(&repo, control).await
+}
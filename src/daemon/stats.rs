@@ -0,0 +1,112 @@
+use super::repo_config::RepoCfg;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use log::info;
+
+/// Counters gathered between two summary log lines
+#[derive(Default)]
+pub struct Stats {
+    inner: Mutex<Counters>,
+    pushed: Mutex<PushWindow>,
+}
+
+/// What happened since the last metrics push
+#[derive(Default)]
+pub struct PushWindow {
+    pub checks: u64,
+    pub updates: u64,
+    pub failures: u64,
+    pub fetch_times: Vec<Duration>,
+}
+
+#[derive(Default)]
+struct Counters {
+    checks: u64,
+    updates: u64,
+    failures: u64,
+    /// Slowest fetch and the name of its repo
+    slowest_fetch: Option<(Duration, String)>,
+    /// Keyed by path, which stays put while a name may change on reload
+    next_checks: BTreeMap<PathBuf, (String, Instant)>,
+}
+
+impl Stats {
+    /// Record a finished check and when the repo will be checked next
+    pub fn record_check(&self, repo: &RepoCfg, fetch_time: Option<Duration>, updated: bool, next: Instant) {
+        let mut counters = self.inner.lock().unwrap();
+        counters.checks += 1;
+        if updated {
+            counters.updates += 1;
+        }
+        if fetch_time.is_none() {
+            counters.failures += 1;
+        }
+        if let Some(fetch_time) = fetch_time {
+            if counters.slowest_fetch.as_ref().is_none_or(|(slowest, _)| fetch_time > *slowest) {
+                counters.slowest_fetch = Some((fetch_time, repo.name().into_owned()));
+            }
+        }
+        counters.next_checks.insert(repo.path.clone(), (repo.name().into_owned(), next));
+        drop(counters);
+
+        let mut window = self.pushed.lock().unwrap();
+        window.checks += 1;
+        if updated {
+            window.updates += 1;
+        }
+        match fetch_time {
+            Some(fetch_time) => window.fetch_times.push(fetch_time),
+            None => window.failures += 1,
+        }
+    }
+
+    /// Stop counting repos removed from the config as watched
+    pub fn forget(&self, repos: &[PathBuf]) {
+        let mut counters = self.inner.lock().unwrap();
+        counters.next_checks.retain(|repo, _| !repos.contains(repo));
+    }
+
+    /// Hand over the counters gathered since the last metrics push
+    pub fn take_push_window(&self) -> PushWindow {
+        std::mem::take(&mut *self.pushed.lock().unwrap())
+    }
+
+    /// Number of repos with a scheduled check
+    pub fn repos_watched(&self) -> usize {
+        self.inner.lock().unwrap().next_checks.len()
+    }
+
+    /// Log the summary for the elapsed period and start a new one
+    pub fn log_summary(&self, period: Duration) {
+        let mut counters = self.inner.lock().unwrap();
+        let now = Instant::now();
+
+        let slowest = match &counters.slowest_fetch {
+            Some((time, repo)) => format!("{:.1}s ({})", time.as_secs_f64(), repo),
+            None => "n/a".to_string(),
+        };
+        let next: Vec<String> = counters
+            .next_checks
+            .values()
+            .map(|(repo, at)| format!("{} in {}s", repo, at.saturating_duration_since(now).as_secs()))
+            .collect();
+
+        info!(
+            "Summary for the last {}h: {} repos watched, {} checks, {} updates, {} failures, slowest fetch {}, next checks: {}",
+            period.as_secs() / 3600,
+            counters.next_checks.len(),
+            counters.checks,
+            counters.updates,
+            counters.failures,
+            slowest,
+            if next.is_empty() { "none scheduled".to_string() } else { next.join(", ") }
+        );
+
+        counters.checks = 0;
+        counters.updates = 0;
+        counters.failures = 0;
+        counters.slowest_fetch = None;
+    }
+}
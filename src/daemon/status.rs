@@ -0,0 +1,150 @@
+use super::config::TlsCfg;
+use super::errors::Result;
+use super::git_ops::{self, CheckOutcome};
+use super::hooks;
+use super::http::{self, Handler, Response};
+use super::repo_config::RepoCfg;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use log::warn;
+use serde_json::{json, Value};
+
+/// Last known state of every repo, rewritten to `status_file` after each
+/// check and served on `status_listen`
+pub struct StatusBoard {
+    path: Option<PathBuf>,
+    repos: Mutex<BTreeMap<PathBuf, RepoStatus>>,
+}
+
+#[derive(Default)]
+struct RepoStatus {
+    name: Option<String>,
+    branch: String,
+    sha: Option<String>,
+    /// Commits fetched but not deployed (held, locked out or refused)
+    behind: Option<u64>,
+    last_check: String,
+    last_update: Option<String>,
+    error: Option<String>,
+    /// Last lines the failing hook printed
+    hook_output: Option<Vec<String>>,
+    paused: Option<String>,
+}
+
+impl StatusBoard {
+    pub fn new(path: Option<PathBuf>) -> StatusBoard {
+        StatusBoard { path, repos: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Note the outcome of a check and write the file out again
+    pub fn record(&self, repo: &RepoCfg, result: &Result<CheckOutcome>) {
+        let now = jiff::Timestamp::now().to_string();
+        // Git runs before the lock, so a slow repo does not hold up everyone else's record
+        let sha = git_ops::current_head(&repo.path).ok();
+        let behind = git_ops::commits_behind(repo);
+        let hook_output = if result.is_err() { hooks::take_failed_output(&repo.path) } else { None };
+
+        let mut repos = self.repos.lock().unwrap();
+        let status = repos.entry(repo.path.clone()).or_default();
+        status.name.clone_from(&repo.name);
+        status.branch = repo.branch().to_string();
+        status.sha = sha;
+        status.behind = behind;
+        status.last_check = now.clone();
+        match result {
+            Ok(outcome) => {
+                status.error = None;
+                status.hook_output = None;
+                if outcome.update.is_some() {
+                    status.last_update = Some(now);
+                }
+            }
+            Err(e) => {
+                status.error = Some(e.to_string());
+                status.hook_output = hook_output;
+            }
+        }
+        self.write(&repos);
+    }
+
+    /// Drop repos no longer in the config
+    pub fn forget(&self, paths: &[PathBuf]) {
+        let mut repos = self.repos.lock().unwrap();
+        repos.retain(|path, _| !paths.contains(path));
+        self.write(&repos);
+    }
+
+    /// Note why a repo stopped being checked, or that it resumed
+    pub fn record_pause(&self, repo: &RepoCfg, reason: Option<String>) {
+        let mut repos = self.repos.lock().unwrap();
+        repos.entry(repo.path.clone()).or_default().paused = reason;
+        self.write(&repos);
+    }
+
+    fn write(&self, repos: &BTreeMap<PathBuf, RepoStatus>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Err(e) = write(path, &to_json(repos)) {
+            warn!("Could not write status file {}: {}", path.display(), e);
+        }
+    }
+
+    /// Repos whose last check failed or that are paused
+    fn unhealthy(&self) -> Vec<String> {
+        let repos = self.repos.lock().unwrap();
+        repos
+            .iter()
+            .filter(|(_, status)| status.error.is_some() || status.paused.is_some())
+            .map(|(path, _)| path.display().to_string())
+            .collect()
+    }
+}
+
+fn to_json(repos: &BTreeMap<PathBuf, RepoStatus>) -> Value {
+    let repos: Vec<_> = repos
+        .iter()
+        .map(|(path, status)| json!({
+            "path": path,
+            "name": status.name,
+            "branch": status.branch,
+            "sha": status.sha,
+            "behind": status.behind,
+            "last_check": status.last_check,
+            "last_update": status.last_update,
+            "error": status.error,
+            "hook_output": status.hook_output,
+            "paused": status.paused,
+        }))
+        .collect();
+    json!({
+        "updated_at": jiff::Timestamp::now().to_string(),
+        "repos": repos,
+    })
+}
+
+/// Write next to the target and rename so readers never see a half-written file
+fn write(path: &Path, content: &Value) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, format!("{content:#}\n"))?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Serve `/healthz` (200 while no repo is failing or paused, 503 otherwise)
+/// and `/status` (the same JSON as the status file) on `addr`
+pub async fn serve(addr: SocketAddr, tls: Option<TlsCfg>, board: Arc<StatusBoard>) -> Result<()> {
+    let handler: Handler = Arc::new(move |request| match request.path.as_str() {
+        _ if request.method != "GET" && request.method != "HEAD" => Response::text(405, "method not allowed"),
+        "/healthz" => match board.unhealthy() {
+            failing if failing.is_empty() => Response::text(200, "ok"),
+            failing => Response::text(503, &format!("failing: {}", failing.join(", "))),
+        },
+        "/status" => Response::json(200, &to_json(&board.repos.lock().unwrap())),
+        _ => Response::text(404, "not found"),
+    });
+    http::serve(addr, tls, handler).await
+}
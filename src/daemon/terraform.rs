@@ -0,0 +1,100 @@
+use super::errors::{Result, WatchError};
+use super::git_ops;
+use super::hooks;
+use super::limits;
+use super::repo_config::{RepoCfg, TerraformCfg};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::info;
+
+/// Saved plan, kept in the git dir so it never shows up in the checkout,
+/// until `rustpdater approve` applies it when `auto_apply` is off
+const PLAN_FILE: &str = "rustpdater.tfplan";
+
+/// Run `terraform plan` (and `apply` when configured) after an infra repo updated
+pub fn plan_and_apply(repo: &RepoCfg, cfg: &TerraformCfg) -> Result<()> {
+    let dir = work_dir(repo, cfg);
+    let plan_file = git_ops::git_path(&repo.path, PLAN_FILE)?;
+    let plan_arg = plan_file.display().to_string();
+
+    run(repo, cfg, &dir, &["init", "-input=false", "-no-color"])?;
+    let (code, output) = run(repo, cfg, &dir, &["plan", "-input=false", "-no-color", "-detailed-exitcode", "-out", &plan_arg])?;
+
+    // Exit code 0 means the plan is empty, 2 means there is something to apply
+    info!("{} plan for {}: {}", cfg.binary, dir.display(), plan_summary(&output));
+    if code == Some(0) {
+        let _ = fs::remove_file(&plan_file);
+        return Ok(());
+    }
+
+    if cfg.auto_apply {
+        info!("Applying {} plan for {}", cfg.binary, dir.display());
+        apply(repo, cfg, &dir, &plan_file)?;
+    } else {
+        info!("Plan for {} awaits approval: run `rustpdater approve {}`", dir.display(), repo.name());
+    }
+
+    Ok(())
+}
+
+/// Apply the plan left for approval, if there is one; false when there is none
+pub fn apply_held_plan(repo: &RepoCfg, cfg: &TerraformCfg) -> Result<bool> {
+    let plan_file = git_ops::git_path(&repo.path, PLAN_FILE)?;
+    if !plan_file.exists() {
+        return Ok(false);
+    }
+    let dir = work_dir(repo, cfg);
+    info!("Applying held {} plan for {}", cfg.binary, dir.display());
+    apply(repo, cfg, &dir, &plan_file)?;
+    Ok(true)
+}
+
+/// Apply a saved plan, which terraform only accepts once
+fn apply(repo: &RepoCfg, cfg: &TerraformCfg, dir: &Path, plan_file: &Path) -> Result<()> {
+    let plan_arg = plan_file.display().to_string();
+    let applied = run(repo, cfg, dir, &["apply", "-input=false", "-no-color", &plan_arg]);
+    let _ = fs::remove_file(plan_file);
+    applied?;
+    info!("{} apply finished for {}", cfg.binary, dir.display());
+    Ok(())
+}
+
+fn work_dir(repo: &RepoCfg, cfg: &TerraformCfg) -> PathBuf {
+    match &cfg.dir {
+        Some(dir) => repo.path.join(dir),
+        None => repo.path.clone(),
+    }
+}
+
+/// Run one terraform subcommand like a hook step: output logged line by line,
+/// killed after `hook_timeout_secs`. The plan's "changes present" exit code
+/// counts as success. Returns the exit code and the last lines of output.
+fn run(repo: &RepoCfg, cfg: &TerraformCfg, dir: &Path, args: &[&str]) -> Result<(Option<i32>, Vec<String>)> {
+    info!("Executing command: {} {} (in directory: {})", cfg.binary, args.join(" "), dir.display());
+    let mut command = limits::command(repo, &cfg.binary);
+    command.args(args).env("TF_IN_AUTOMATION", "1").current_dir(dir);
+    let timeout = repo.hook_timeout_secs.map(Duration::from_secs);
+    let (status, output) = hooks::run_logged(repo, command, timeout)?;
+    let command = format!("{} {}", cfg.binary, args[0]);
+
+    let Some(status) = status else {
+        return Err(WatchError::HookTimeout { command, timeout: format!("{}s", timeout.unwrap_or_default().as_secs()) });
+    };
+    let is_plan_with_changes = args[0] == "plan" && status.code() == Some(2);
+    if !status.success() && !is_plan_with_changes {
+        return Err(WatchError::CommandFailed { command, stderr: output.join("\n") });
+    }
+
+    Ok((status.code(), output))
+}
+
+/// Pick the "Plan: N to add, ..." or "No changes." line out of the plan output
+fn plan_summary(output: &[String]) -> String {
+    output
+        .iter()
+        .map(|line| line.trim())
+        .find(|line| line.starts_with("Plan:") || line.starts_with("No changes."))
+        .unwrap_or("plan finished without a summary line")
+        .to_string()
+}
@@ -0,0 +1,87 @@
+use super::config::Config;
+use super::git_ops;
+use super::repo_config::RepoCfg;
+
+/// Check what the watcher needs from every repo without starting it: a
+/// checkout (or a `url` to clone one), a reachable remote with the repo's
+/// credentials and the tracked branch on it. Prints one line per finding and
+/// returns how many problems were found.
+pub fn run(config: &Config) -> usize {
+    let mut problems = 0;
+    for warning in &config.warnings {
+        println!("warning: {warning}");
+    }
+
+    for repo in &config.repos {
+        println!("{}", repo.name());
+        for finding in check_repo(repo) {
+            match finding {
+                Ok(note) => println!("  ok: {note}"),
+                Err(problem) => {
+                    println!("  error: {problem}");
+                    problems += 1;
+                }
+            }
+        }
+    }
+
+    if config.repos.is_empty() {
+        println!("warning: no repos configured");
+    }
+    problems
+}
+
+fn check_repo(repo: &RepoCfg) -> Vec<Result<String, String>> {
+    let mut findings = Vec::new();
+    let mut remote_repo = repo.clone();
+    if git_ops::is_checkout(repo) {
+        findings.push(Ok("git checkout".to_string()));
+    } else if repo.path.exists() && repo.path.read_dir().is_ok_and(|mut entries| entries.next().is_some()) {
+        findings.push(Err("exists but is not a git checkout; empty it or point `path` somewhere else".to_string()));
+        return findings;
+    } else if let Some(url) = &repo.url {
+        findings.push(Ok(format!("missing, will be cloned from {url}")));
+        // ls-remote runs in the repo's directory, which doesn't exist yet
+        remote_repo.path = std::env::temp_dir();
+    } else {
+        findings.push(Err("missing and no `url` to clone it from".to_string()));
+        return findings;
+    }
+
+    // Asking the remote for the tracked refs doubles as the credential dry-run
+    if let Some(glob) = &repo.ref_glob {
+        match git_ops::remote_refs(&remote_repo, glob) {
+            Ok(refs) if refs.is_empty() => findings.push(Err(format!(
+                "remote reachable but no ref matches '{glob}'; fix `ref_glob` or push one first"
+            ))),
+            Ok(refs) => findings.push(Ok(format!("remote reachable, tracking the newest of {} ref(s) matching '{glob}'", refs.len()))),
+            Err(e) => findings.push(Err(format!("remote not reachable with this repo's credentials: {e}"))),
+        }
+        return findings;
+    }
+    let branch = match repo.branch.as_deref() {
+        Some(branch) if branch != "HEAD" => branch.to_string(),
+        // The remote's HEAD is followed, only reachability is left to check
+        branch => match git_ops::detect_default_branch(&remote_repo) {
+            Ok(default) if branch.is_some() => {
+                findings.push(Ok(format!("remote reachable, following its default branch (now '{default}')")));
+                return findings;
+            }
+            Ok(default) => default,
+            Err(e) => {
+                findings.push(Err(format!("remote not reachable with this repo's credentials: {e}")));
+                return findings;
+            }
+        },
+    };
+    match git_ops::remote_has_branch(&remote_repo, &branch) {
+        Ok(true) => findings.push(Ok(format!("remote reachable, branch '{branch}' exists"))),
+        Ok(false) => findings.push(Err(format!(
+            "remote reachable but has no branch '{branch}'; fix `branch` or push it first"
+        ))),
+        Err(e) => findings.push(Err(format!(
+            "remote not reachable with this repo's credentials (ssh_key, credentials, askpass): {e}"
+        ))),
+    }
+    findings
+}
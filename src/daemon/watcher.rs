@@ -1,41 +1,797 @@
-use super::errors::Result;
-use super::repo_config::RepoCfg;
+use super::config::Config;
+use super::duration::parse_duration;
+use super::errors::{Result, WatchError};
+use super::repo_config::{Priority, RepoCfg};
 use super::git_ops;
+use super::control;
+use super::drift;
+use super::metrics;
+use super::heartbeat;
+use super::status::{self, StatusBoard};
+use super::stats::Stats;
+use super::webhook::{self, Targets};
+use tokio::sync::{oneshot, watch, Notify};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::{task, time};
-use std::time::Duration;
-use log::{error, info};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{error, info, warn};
 
-pub async fn start_watching_repos(repos: &[RepoCfg]) -> Result<()> {
-    let mut tasks = Vec::new();
+/// Shared state and optional hooks for a repo's poll loop
+#[derive(Clone, Default)]
+pub struct LoopControl {
+    /// Counters for the periodic summary
+    pub stats: Arc<Stats>,
+    /// Flipped to true after the first successful check
+    pub ready: Arc<AtomicBool>,
+    /// Sentinel file written after the first successful check, a relative
+    /// path is taken inside the repo's git dir
+    pub ready_file: Option<PathBuf>,
+    /// Stops the loop between checks once it reads true
+    pub shutdown: Option<watch::Receiver<bool>>,
+    /// Where each check's outcome is summarized for other tools
+    pub status: Option<Arc<StatusBoard>>,
+    /// Limit on checks running at once
+    pub queue: Option<Arc<CheckQueue>>,
+    /// Window in which a repeated check error is counted instead of logged again
+    pub failure_log_window: Option<Duration>,
+    /// Checks asked for ahead of their interval, e.g. by the webhook listener
+    pub triggers: Option<Arc<Triggers>>,
+    /// Cuts this loop's sleep short when one of its repos is triggered
+    pub wake: Option<Arc<Notify>>,
+}
 
-    info!("Starting watcher with {} repos", repos.len());
+impl LoopControl {
+    fn mark_ready(&self, repo: &RepoCfg) {
+        if self.ready.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        info!("First sync of {} complete", repo.name());
+        if let Some(file) = &self.ready_file {
+            let file = match git_ops::git_dir(&repo.path) {
+                Ok(git_dir) => git_dir.join(file),
+                Err(_) => file.clone(),
+            };
+            if let Err(e) = std::fs::write(&file, b"ready\n") {
+                warn!("Could not write ready file {}: {}", file.display(), e);
+            }
+        }
+    }
 
-    for repo in repos {
-        let repo = repo.clone();
-        tasks.push(task::spawn(async move { watch_single_repo(&repo).await }));
+    /// Wait for a check slot when checks are limited
+    async fn slot(&self, priority: Priority) -> Option<CheckSlot> {
+        match &self.queue {
+            Some(queue) => Some(queue.acquire(priority).await),
+            None => None,
+        }
+    }
+
+    /// Sleep for `duration`, returning true if shutdown was requested meanwhile.
+    /// A trigger for one of the loop's repos ends the sleep early.
+    async fn sleep_or_shutdown(&mut self, duration: Duration) -> bool {
+        let shutdown = async {
+            match &mut self.shutdown {
+                Some(shutdown) if *shutdown.borrow() => {}
+                Some(shutdown) => {
+                    let _ = shutdown.changed().await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let wake = async {
+            match &self.wake {
+                Some(wake) => wake.notified().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            biased;
+            _ = shutdown => true,
+            _ = wake => false,
+            _ = time::sleep(duration) => false,
+        }
+    }
+
+    /// What asked for a check of `path` since the last call, if anything did
+    fn take_trigger(&self, path: &Path) -> Option<&'static str> {
+        self.triggers.as_ref().and_then(|triggers| triggers.take(path))
+    }
+}
+
+/// Lets something outside the poll loops ask for a repo to be checked now
+/// instead of at its next interval
+#[derive(Default)]
+pub struct Triggers {
+    loops: Mutex<Vec<(PathBuf, Arc<Notify>)>>,
+    /// Repos asked for and what asked, e.g. "webhook"
+    requested: Mutex<HashMap<PathBuf, &'static str>>,
+}
+
+impl Triggers {
+    /// Register a loop watching `paths`, returning what wakes it
+    fn register(&self, paths: &[PathBuf]) -> Arc<Notify> {
+        let wake = Arc::new(Notify::new());
+        let mut loops = self.loops.lock().unwrap();
+        loops.extend(paths.iter().map(|path| (path.clone(), wake.clone())));
+        wake
+    }
+
+    /// Forget a stopped loop
+    fn unregister(&self, wake: &Arc<Notify>) {
+        self.loops.lock().unwrap().retain(|(_, registered)| !Arc::ptr_eq(registered, wake));
+    }
+
+    /// Ask for a check of the repo at `path` on behalf of `source`, which ends
+    /// up as the update's trigger; false when no loop watches it
+    pub fn trigger(&self, path: &Path, source: &'static str) -> bool {
+        let loops = self.loops.lock().unwrap();
+        let Some((_, wake)) = loops.iter().find(|(watched, _)| watched == path) else {
+            return false;
+        };
+        self.requested.lock().unwrap().insert(path.to_path_buf(), source);
+        // Stores a permit when the loop is busy checking, so it checks again right after
+        wake.notify_one();
+        true
+    }
+
+    /// Paths of the repos some loop watches
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.loops.lock().unwrap().iter().map(|(path, _)| path.clone()).collect()
+    }
+
+    fn take(&self, path: &Path) -> Option<&'static str> {
+        self.requested.lock().unwrap().remove(path)
+    }
+}
+
+/// Caps how many checks run at once. A freed slot goes to the waiting check
+/// with the highest priority, and to the longest waiting one among equals.
+pub struct CheckQueue {
+    limit: usize,
+    state: Mutex<QueueState>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    running: usize,
+    next_ticket: u64,
+    waiting: BinaryHeap<(Priority, Reverse<u64>, Waiter)>,
+}
+
+/// Wakes a waiting check; ordered only by the fields before it in the heap
+struct Waiter(oneshot::Sender<()>);
+
+impl PartialEq for Waiter {
+    fn eq(&self, _: &Self) -> bool { true }
+}
+impl Eq for Waiter {}
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for Waiter {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering { std::cmp::Ordering::Equal }
+}
+
+/// A running check's slot, handed on when dropped
+pub struct CheckSlot {
+    queue: Arc<CheckQueue>,
+}
+
+impl CheckQueue {
+    pub fn new(limit: usize) -> Arc<CheckQueue> {
+        Arc::new(CheckQueue { limit: limit.max(1), state: Mutex::default() })
+    }
+
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> CheckSlot {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < self.limit {
+                state.running += 1;
+                return CheckSlot { queue: self.clone() };
+            }
+            let (sender, receiver) = oneshot::channel();
+            let ticket = state.next_ticket;
+            state.next_ticket += 1;
+            state.waiting.push((priority, Reverse(ticket), Waiter(sender)));
+            receiver
+        };
+        // The slot is handed over as is, `running` already counts it
+        let _ = receiver.await;
+        CheckSlot { queue: self.clone() }
+    }
+}
+
+impl Drop for CheckSlot {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        while let Some((_, _, Waiter(sender))) = state.waiting.pop() {
+            if sender.send(()).is_ok() {
+                return;
+            }
+        }
+        state.running -= 1;
+    }
+}
+
+/// Run every repo's poll loop. On SIGHUP the config is read again with
+/// `reload`: loops of removed or changed repos are stopped, new and changed
+/// ones started, untouched ones keep running. Global settings apply at the
+/// next restart.
+pub async fn start_watching_repos(config: &Config, reload: Option<&dyn Fn() -> Result<Config>>) -> Result<()> {
+    let control = LoopControl {
+        status: (config.status_file.is_some() || config.status_listen.is_some())
+            .then(|| Arc::new(StatusBoard::new(config.status_file.clone()))),
+        queue: config.max_concurrent_checks.map(CheckQueue::new),
+        failure_log_window: config.failure_log_window.as_deref().and_then(parse_duration),
+        triggers: (config.webhook.is_some() || config.control_socket().is_some()).then(Arc::default),
+        ..Default::default()
+    };
+
+    info!("Starting watcher with {} repos", config.repos.len());
+
+    if config.stats_interval_hours > 0 {
+        let period = Duration::from_secs(config.stats_interval_hours * 3600);
+        let stats = control.stats.clone();
+        task::spawn(async move {
+            let mut ticker = time::interval_at(time::Instant::now() + period, period);
+            loop {
+                ticker.tick().await;
+                stats.log_summary(period);
+            }
+        });
+    }
+
+    if let Some(metrics) = &config.metrics {
+        task::spawn(metrics::push_loop(metrics.clone(), control.stats.clone()));
     }
 
-    for task in tasks {
-        task.await??;
+    if let (Some(listen), Some(board)) = (config.status_listen, &control.status) {
+        let (tls, board) = (config.status_tls.clone(), board.clone());
+        task::spawn(async move {
+            if let Err(e) = status::serve(listen, tls, board).await {
+                error!("Status listener on {} failed: {}", listen, e);
+            }
+        });
     }
 
+    if let (Some(socket), Some(triggers)) = (config.control_socket(), &control.triggers) {
+        let (socket, triggers) = (socket.to_path_buf(), triggers.clone());
+        task::spawn(async move {
+            if let Err(e) = control::serve(&socket, triggers).await {
+                warn!("Control socket {} unavailable, `rustpdater update` won't work: {}", socket.display(), e);
+            }
+        });
+    }
+
+    let targets = config.webhook.as_ref().map(|webhook| Arc::new(Targets::new(webhook, &config.repos)));
+    if let (Some(webhook), Some(targets), Some(triggers)) = (&config.webhook, &targets, &control.triggers) {
+        let (webhook, targets, triggers) = (webhook.clone(), targets.clone(), triggers.clone());
+        task::spawn(async move {
+            if let Err(e) = webhook::serve(webhook, targets, triggers).await {
+                error!("Webhook listener failed: {}", e);
+            }
+        });
+    }
+
+    let ramp = config.startup_ramp.as_deref().and_then(parse_duration).unwrap_or_default();
+    let units = loop_units(config);
+    let priorities: Vec<_> = units.iter().map(|unit| unit.iter().map(|r| r.priority).max().unwrap_or_default()).collect();
+    let mut running: Vec<RunningLoop> = units
+        .into_iter()
+        .zip(ramp_delays(&priorities, ramp))
+        .map(|(repos, delay)| RunningLoop::spawn(repos, &control, delay))
+        .collect();
+
+    let mut current = config.clone();
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {}
+            _ = terminate.recv() => {
+                info!("Received SIGTERM, stopping");
+                break;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("Received SIGINT, stopping");
+                break;
+            }
+        }
+        let Some(reload) = reload else {
+            info!("Received SIGHUP, but there is no config file to reload");
+            continue;
+        };
+        info!("Received SIGHUP, reloading the config");
+        let config = match reload() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Could not reload the config, keeping the running one: {}", e);
+                continue;
+            }
+        };
+        let globals = |config: &Config| format!("{:?}", Config { repos: Vec::new(), warnings: Vec::new(), ..config.clone() });
+        if globals(&config) != globals(&current) {
+            warn!("Global settings changed; they apply at the next restart, only repos were reloaded");
+        }
+        reload_loops(&mut running, loop_units(&config), &control).await;
+        if let Some(targets) = &targets {
+            targets.replace(&config.repos);
+        }
+        current = config;
+    }
+
+    let timeout = parse_duration(&current.shutdown_timeout).unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+    stop_loops(running, timeout).await;
+    if let Some(socket) = config.control_socket() {
+        let _ = std::fs::remove_file(socket);
+    }
     Ok(())
 }
 
-async fn watch_single_repo(repo: &RepoCfg) -> Result<()> {
-    let interval = Duration::from_secs(repo.interval);
-    info!("Watching repo '{}' (branch '{}') every {}s\n", repo.path.display(), repo.branch, repo.interval);
+/// Ask every loop to stop and wait for running checks, hooks included, to
+/// finish. Loops still busy after `timeout` are left behind when the process exits.
+async fn stop_loops(running: Vec<RunningLoop>, timeout: Duration) {
+    for running in &running {
+        let _ = running.stop.send(true);
+    }
+    let busy = running.iter().filter(|running| !running.task.is_finished()).count();
+    if busy > 0 {
+        info!("Waiting up to {}s for {} running check(s) to finish", timeout.as_secs(), busy);
+    }
+    let all_stopped = async {
+        for running in running {
+            let _ = running.task.await;
+        }
+    };
+    if time::timeout(timeout, all_stopped).await.is_err() {
+        warn!("Checks still running after {}s, exiting anyway", timeout.as_secs());
+    }
+}
+
+/// Wait used when `shutdown_timeout` doesn't parse
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A repo poll loop (or a host group's) and how to stop it
+struct RunningLoop {
+    /// The loop's repos as configured, compared on reload to spot changes
+    key: String,
+    paths: Vec<PathBuf>,
+    stop: watch::Sender<bool>,
+    wake: Option<Arc<Notify>>,
+    task: task::JoinHandle<()>,
+}
+
+impl RunningLoop {
+    fn spawn(repos: Vec<RepoCfg>, control: &LoopControl, delay: Duration) -> RunningLoop {
+        let key = format!("{repos:?}");
+        let paths: Vec<PathBuf> = repos.iter().map(|repo| repo.path.clone()).collect();
+        let (stop, stopped) = watch::channel(false);
+        let mut control = LoopControl { shutdown: Some(stopped), ..control.clone() };
+        control.wake = control.triggers.as_ref().map(|triggers| triggers.register(&paths));
+        let wake = control.wake.clone();
+
+        let watcher = task::spawn(async move {
+            if control.sleep_or_shutdown(delay).await {
+                return Ok(());
+            }
+            watch_repo_group(&repos, control).await
+        });
+        // A repo loop that ends, with an error or a panic, never stops the others
+        let task = task::spawn(async move {
+            match watcher.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("A repo watcher stopped: {}", e),
+                Err(e) => error!("A repo watcher crashed: {}", e),
+            }
+        });
+        RunningLoop { key, paths, stop, wake, task }
+    }
+}
+
+/// Repos checked by one loop each: a single repo, or a host group with `group_by_host`
+fn loop_units(config: &Config) -> Vec<Vec<RepoCfg>> {
+    if config.group_by_host {
+        group_by_host(&config.repos)
+    } else {
+        config.repos.iter().map(|repo| vec![repo.clone()]).collect()
+    }
+}
+
+/// Stop the loops whose repos are gone or changed and start the new ones
+async fn reload_loops(running: &mut Vec<RunningLoop>, units: Vec<Vec<RepoCfg>>, control: &LoopControl) {
+    let keys: Vec<String> = units.iter().map(|repos| format!("{repos:?}")).collect();
+    let (kept, stopped): (Vec<_>, Vec<_>) = running.drain(..).partition(|running| keys.contains(&running.key));
+
+    let stopped_count = stopped.len();
+    let mut removed = Vec::new();
+    for old in stopped {
+        let _ = old.stop.send(true);
+        // Let a running check finish so its replacement never overlaps it
+        let _ = old.task.await;
+        if let (Some(triggers), Some(wake)) = (&control.triggers, &old.wake) {
+            triggers.unregister(wake);
+        }
+        removed.extend(old.paths.into_iter().filter(|path| !units.iter().flatten().any(|repo| repo.path == *path)));
+    }
+    control.stats.forget(&removed);
+    if let (Some(status), false) = (&control.status, removed.is_empty()) {
+        status.forget(&removed);
+    }
+
+    *running = kept;
+    let kept_count = running.len();
+    for (repos, key) in units.into_iter().zip(keys) {
+        if !running.iter().any(|running| running.key == key) {
+            running.push(RunningLoop::spawn(repos, control, Duration::ZERO));
+        }
+    }
+    info!(
+        "Config reloaded: {} loop(s) unchanged, {} stopped, {} started",
+        kept_count,
+        stopped_count,
+        running.len() - kept_count
+    );
+}
+
+/// Spread the first checks evenly over `ramp`, highest priority first and in
+/// config order among equals
+fn ramp_delays(priorities: &[Priority], ramp: Duration) -> Vec<Duration> {
+    let mut order: Vec<usize> = (0..priorities.len()).collect();
+    order.sort_by_key(|&i| Reverse(priorities[i]));
+
+    let mut delays = vec![Duration::ZERO; priorities.len()];
+    for (position, &index) in order.iter().enumerate() {
+        delays[index] = ramp.mul_f64(position as f64 / priorities.len() as f64);
+    }
+    delays
+}
+
+/// Split repos into groups sharing a remote host, keeping config order.
+/// Repos without a recognisable host each get a group of their own.
+fn group_by_host(repos: &[RepoCfg]) -> Vec<Vec<RepoCfg>> {
+    let mut groups: Vec<(Option<String>, Vec<RepoCfg>)> = Vec::new();
+    for repo in repos {
+        let host = git_ops::remote_host(repo);
+        match groups.iter_mut().find(|(h, _)| host.is_some() && *h == host) {
+            Some((_, group)) => group.push(repo.clone()),
+            None => groups.push((host, vec![repo.clone()])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// Clone if needed, settle which branch to track and make sure the remote is
+/// reachable before polling
+fn prepare(repo: &RepoCfg) -> Result<RepoCfg> {
+    let repo = checkout(repo)?;
+    info!("Watching repo '{}' (branch '{}') every {}s\n", repo.name(), repo.branch(), repo.interval);
 
     // Let's first test the SSH connection by doing a git pull in a temp folder
-    if let Err(e) = git_ops::test_git_pull_in_tmp(&repo.path) {
-        error!("Git pull test failed for {}: {}", repo.path.display(), e);
+    if let Err(e) = git_ops::test_git_pull_in_tmp(&repo) {
+        error!("Git pull test failed for {}: {}", repo.name(), e);
+        return Err(e);
+    }
+    Ok(repo)
+}
+
+/// Clone if needed and settle which branch to track
+fn checkout(repo: &RepoCfg) -> Result<RepoCfg> {
+    // Make sure there is a checkout to watch
+    if let Err(e) = git_ops::clone_if_missing(repo) {
+        error!("Initial clone failed for {}: {}", repo.name(), e);
+        git_ops::report_failure(repo, &e, &git_ops::hook_vars(repo, repo.branch(), "", ""));
         return Err(e);
     }
 
+    let mut repo = repo.clone();
+    if repo.branch.is_none() && repo.ref_glob.is_none() {
+        match git_ops::detect_default_branch(&repo) {
+            Ok(branch) => {
+                info!("No branch configured for {}, tracking the remote default '{}'", repo.name(), branch);
+                repo.branch = Some(branch);
+            }
+            Err(e) => warn!("Could not detect the default branch of {}, tracking the remote HEAD: {}", repo.name(), e),
+        }
+    }
+    Ok(repo)
+}
+
+/// Check every repo a single time, one after another, as cron or CI would.
+/// Returns the names of the repos whose check failed.
+pub fn run_once(config: &Config) -> Vec<String> {
+    let control = LoopControl {
+        status: config.status_file.clone().map(|path| Arc::new(StatusBoard::new(Some(path)))),
+        ..Default::default()
+    };
+    let mut failed = Vec::new();
+    for repo in &config.repos {
+        let mut repo = match checkout(repo) {
+            Ok(repo) => repo,
+            Err(_) => {
+                failed.push(repo.name().into_owned());
+                continue;
+            }
+        };
+        let mut failures = Failures::default();
+        check(&mut repo, &control, Instant::now(), &Mutex::new(()), &mut failures, "run-once");
+        if failures.count > 0 {
+            failed.push(repo.name().into_owned());
+        }
+    }
+    failed
+}
+
+/// Start the working tree watch on its own thread when the repo asks for it.
+/// The watch ends with the loop, so a reload never leaves an old one behind.
+/// Returns the lock the poll loop holds while checking.
+fn watch_files(repo: &RepoCfg, control: &LoopControl) -> Arc<Mutex<()>> {
+    let busy = Arc::new(Mutex::new(()));
+    if repo.watch_files {
+        let repo = repo.clone();
+        let busy = busy.clone();
+        let stop = control.shutdown.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = drift::watch_tree(&repo, busy, stop) {
+                error!("Could not watch the working tree of {}: {}", repo.name(), e);
+            }
+        });
+    }
+    busy
+}
+
+/// Run one check and record its outcome. `trigger` is what started it, e.g. "poll".
+fn check(repo: &mut RepoCfg, control: &LoopControl, next_check: Instant, busy: &Mutex<()>, failures: &mut Failures, trigger: &'static str) {
+    let _guard = busy.lock().unwrap();
+    let result = git_ops::start_watching(repo, trigger);
+    let succeeded = result.is_ok();
+    if let Some(url) = &repo.heartbeat {
+        heartbeat::ping(repo, url, result.is_ok());
+    }
+    if let Some(status) = &control.status {
+        status.record(repo, &result);
+    }
+    match result {
+        Ok(outcome) => {
+            control.stats.record_check(repo, Some(outcome.fetch_time), outcome.update.is_some(), next_check);
+            control.mark_ready(repo);
+        }
+        Err(error @ WatchError::BranchMissing { .. }) => {
+            control.stats.record_check(repo, None, false, next_check);
+            error!("{}", error);
+            if repo.follow_default {
+                follow_default_branch(repo);
+            } else {
+                error!("Set `branch` to the new name, or `follow_default = true` to track the remote's default branch");
+            }
+        }
+        Err(error) if git_ops::is_corruption(&error) => {
+            control.stats.record_check(repo, None, false, next_check);
+            error!("Repository {} is corrupted, cloning it again: {}", repo.name(), error);
+            match git_ops::reclone_corrupt(repo) {
+                Ok(quarantine) => error!(
+                    "Re-cloned {} after corruption; the broken copy is kept at {}",
+                    repo.name(), quarantine.display()
+                ),
+                Err(e) => error!("Re-clone of {} failed: {}", repo.name(), e),
+            }
+        }
+        Err(error) => {
+            control.stats.record_check(repo, None, false, next_check);
+            failures.log_error(repo, control, &error.to_string());
+        }
+    }
+    failures.record(repo, control, succeeded);
+}
+
+/// Consecutive failed checks of a repo, and since when they paused it
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    paused_at: Option<Instant>,
+    /// Last error logged, how often it repeated since and when it was logged
+    last_error: Option<(String, u32, Instant)>,
+}
+
+impl Failures {
+    /// Whether the repo should skip its check; once `failure_cooldown` has
+    /// passed a paused repo gets one more check
+    fn is_paused(&self, repo: &RepoCfg) -> bool {
+        let Some(paused_at) = self.paused_at else {
+            return false;
+        };
+        match repo.failure_cooldown.as_deref().and_then(parse_duration) {
+            Some(cooldown) => paused_at.elapsed() < cooldown,
+            None => true,
+        }
+    }
+
+    /// Log a check error; within `failure_log_window` a repeat of the previous
+    /// error is only counted, and summarized once the window has passed
+    fn log_error(&mut self, repo: &RepoCfg, control: &LoopControl, error: &str) {
+        if let (Some(window), Some((last, repeats, logged_at))) = (control.failure_log_window, &mut self.last_error) {
+            if last == error {
+                *repeats += 1;
+                if logged_at.elapsed() < window {
+                    return;
+                }
+                error!("watcher error on {} (still failing, occurrence {}): {}", repo.name(), *repeats + 1, error);
+                *logged_at = Instant::now();
+                return;
+            }
+        }
+        error!("watcher error on {}: {}", repo.name(), error);
+        self.last_error = Some((error.to_string(), 0, Instant::now()));
+    }
+
+    fn record(&mut self, repo: &RepoCfg, control: &LoopControl, succeeded: bool) {
+        if succeeded {
+            self.count = 0;
+            self.last_error = None;
+            if self.paused_at.take().is_some() {
+                info!("Checks of {} succeed again, resuming", repo.name());
+                if let Some(status) = &control.status {
+                    status.record_pause(repo, None);
+                }
+            }
+            return;
+        }
+        self.count += 1;
+
+        if self.paused_at.is_some() {
+            // The retry after the cooldown failed too
+            self.paused_at = Some(Instant::now());
+            warn!(
+                "{} still fails after its cooldown, paused for another {}",
+                repo.name(), repo.failure_cooldown.as_deref().unwrap_or_default()
+            );
+            return;
+        }
+        let Some(max) = repo.max_consecutive_failures else {
+            return;
+        };
+        if self.count >= max {
+            self.paused_at = Some(Instant::now());
+            let reason = format!("paused after {} consecutive failed checks", self.count);
+            match &repo.failure_cooldown {
+                Some(cooldown) => error!("Stopped checking {}: {}; retrying once in {}", repo.name(), reason, cooldown),
+                None => error!("Stopped checking {}: {}; fix the cause and restart rustpdater", repo.name(), reason),
+            }
+            if let Some(status) = &control.status {
+                status.record_pause(repo, Some(reason));
+            }
+        }
+    }
+}
+
+/// Move a repo whose branch vanished upstream onto the remote's default branch
+fn follow_default_branch(repo: &mut RepoCfg) {
+    match git_ops::detect_default_branch(repo) {
+        Ok(branch) if Some(&branch) != repo.branch.as_ref() => {
+            warn!("Following the remote default branch of {}: now tracking '{}' instead of '{}'", repo.name(), branch, repo.branch());
+            repo.branch = Some(branch);
+        }
+        Ok(_) => error!("The missing branch of {} is still the remote's default branch", repo.name()),
+        Err(e) => error!("Could not detect the default branch of {}: {}", repo.name(), e),
+    }
+}
+
+/// Wait before a repo's first check: its `startup_delay`, plus one interval
+/// when `check_on_start` is off
+fn first_check_delay(repo: &RepoCfg) -> Duration {
+    let mut delay = repo.startup_delay.as_deref().and_then(parse_duration).unwrap_or_default();
+    if !repo.check_on_start {
+        delay = delay.saturating_add(Duration::from_secs(repo.interval));
+    }
+    delay
+}
+
+pub async fn watch_single_repo(repo: &RepoCfg, mut control: LoopControl) -> Result<()> {
+    let interval = Duration::from_secs(repo.interval);
+    // A repo that can't be set up yet retries on its own schedule, the others keep running
+    let mut repo = loop {
+        match task::block_in_place(|| prepare(repo)) {
+            Ok(repo) => break repo,
+            Err(e) => error!("Could not start watching {}, retrying in {}s: {}", repo.name(), interval.as_secs(), e),
+        }
+        if control.sleep_or_shutdown(interval).await {
+            return Ok(());
+        }
+    };
+    let busy = watch_files(&repo, &control);
+
+    let first_check = first_check_delay(&repo);
+    if !first_check.is_zero() {
+        info!("First check of {} in {}s", repo.name(), first_check.as_secs());
+        if control.sleep_or_shutdown(first_check).await {
+            return Ok(());
+        }
+    }
+
+    let mut failures = Failures::default();
     loop {
-        if let Err(error) = git_ops::start_watching(repo) {
-            error!("watcher error on {}: {}", repo.path.display(), error);
+        let trigger = control.take_trigger(&repo.path).unwrap_or("poll");
+        if !failures.is_paused(&repo) {
+            let slot = control.slot(repo.priority).await;
+            // Off the async worker, so signals and the webhook are served while git and hooks run
+            task::block_in_place(|| check(&mut repo, &control, Instant::now() + interval, &busy, &mut failures, trigger));
+            drop(slot);
+        }
+        if control.sleep_or_shutdown(interval).await {
+            info!("Stopped watching {}", repo.name());
+            return Ok(());
+        }
+    }
+}
+
+/// Poll repos sharing a remote host from one loop, so their checks run
+/// back-to-back on a warm connection instead of scattered over the interval.
+/// Each repo keeps its own interval; one that is due within a tenth of it
+/// is checked early together with the others.
+async fn watch_repo_group(repos: &[RepoCfg], mut control: LoopControl) -> Result<()> {
+    if let [repo] = repos {
+        return watch_single_repo(repo, control).await;
+    }
+    info!("Checking {} repos on the same host back-to-back", repos.len());
+    let mut repos = repos.to_vec();
+    // Repos that failed to set up stay unprepared and are retried when due
+    let mut busy: Vec<Option<Arc<Mutex<()>>>> = repos
+        .iter_mut()
+        .map(|repo| match task::block_in_place(|| prepare(repo)) {
+            Ok(prepared) => {
+                *repo = prepared;
+                Some(watch_files(repo, &control))
+            }
+            Err(e) => {
+                error!("Could not start watching {}, retrying in {}s: {}", repo.name(), repo.interval, e);
+                None
+            }
+        })
+        .collect();
+    let mut failures: Vec<Failures> = repos.iter().map(|_| Failures::default()).collect();
+
+    let mut due: Vec<Instant> = repos.iter().map(|repo| Instant::now() + first_check_delay(repo)).collect();
+    loop {
+        let now = Instant::now();
+        for (((repo, due), busy), failures) in repos.iter_mut().zip(due.iter_mut()).zip(busy.iter_mut()).zip(failures.iter_mut()) {
+            let interval = Duration::from_secs(repo.interval);
+            let trigger = control.take_trigger(&repo.path);
+            if trigger.is_some() {
+                *due = now;
+            }
+            if *due <= now + interval / 10 {
+                *due = now + interval;
+                if failures.is_paused(repo) {
+                    continue;
+                }
+                if busy.is_none() {
+                    match task::block_in_place(|| prepare(repo)) {
+                        Ok(prepared) => {
+                            *repo = prepared;
+                            *busy = Some(watch_files(repo, &control));
+                        }
+                        Err(e) => {
+                            error!("Could not start watching {}, retrying in {}s: {}", repo.name(), repo.interval, e);
+                            continue;
+                        }
+                    }
+                }
+                let Some(busy) = busy else {
+                    continue;
+                };
+                let slot = control.slot(repo.priority).await;
+                task::block_in_place(|| check(repo, &control, *due, busy, failures, trigger.unwrap_or("poll")));
+                drop(slot);
+            }
+        }
+
+        let next = due.iter().min().copied().unwrap_or(now);
+        if control.sleep_or_shutdown(next.saturating_duration_since(Instant::now())).await {
+            info!("Stopped watching {} repos", repos.len());
+            return Ok(());
         }
-        time::sleep(interval).await;
     }
 }
@@ -0,0 +1,350 @@
+use super::allowlist::Allowlist;
+use super::config::WebhookCfg;
+use super::errors::Result;
+use super::git_ops;
+use super::http::{self, Handler, Request, Response};
+use super::repo_config::RepoCfg;
+use super::watcher::Triggers;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use log::{debug, info, warn};
+
+/// Where a delivery comes from, told apart by its event header
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Forge {
+    GitHub,
+    GitLab,
+    Gitea,
+    /// Bitbucket Cloud and Bitbucket Server / Data Center
+    Bitbucket,
+}
+
+impl Forge {
+    /// Gitea and Forgejo also send `X-GitHub-Event`, so they are looked for first
+    fn detect(request: &Request) -> Option<(Forge, &str)> {
+        if let Some(event) = request.header("X-Gitlab-Event") {
+            return Some((Forge::GitLab, event));
+        }
+        if let Some(event) = request.header("X-Event-Key") {
+            return Some((Forge::Bitbucket, event));
+        }
+        if let Some(event) = request.header("X-Forgejo-Event").or_else(|| request.header("X-Gitea-Event")) {
+            return Some((Forge::Gitea, event));
+        }
+        request.header("X-GitHub-Event").map(|event| (Forge::GitHub, event))
+    }
+
+    fn is_push(self, event: &str) -> bool {
+        match self {
+            Forge::GitLab => event == "Push Hook" || event == "Tag Push Hook",
+            Forge::GitHub | Forge::Gitea => event == "push",
+            // Cloud sends repo:push, Server repo:refs_changed
+            Forge::Bitbucket => event == "repo:push" || event == "repo:refs_changed",
+        }
+    }
+
+    /// What the push delivery says was pushed
+    fn parse_push(self, body: &str) -> serde_json::Result<Push> {
+        match self {
+            Forge::Bitbucket => serde_json::from_str::<BitbucketEvent>(body).map(BitbucketEvent::into_push),
+            Forge::GitHub | Forge::GitLab | Forge::Gitea => serde_json::from_str::<PushEvent>(body).map(PushEvent::into_push),
+        }
+    }
+
+    /// Whether the delivery carries the secret configured for its forge
+    fn authorized(self, request: &Request, cfg: &WebhookCfg) -> bool {
+        let (expected, sent) = match self {
+            Forge::GitHub | Forge::Bitbucket => return true,
+            Forge::GitLab => (&cfg.gitlab_token, request.header("X-Gitlab-Token")),
+            Forge::Gitea => (
+                &cfg.gitea_token,
+                request.header("Authorization").map(|value| value.strip_prefix("Bearer ").unwrap_or(value)),
+            ),
+        };
+        match (expected, sent) {
+            (None, _) => true,
+            (Some(expected), Some(sent)) => same_secret(expected, sent),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Whether the delivery was signed with a repo's `secret`: an HMAC-SHA256
+    /// of the body from GitHub, Gitea/Forgejo and Bitbucket, the plain token from GitLab
+    fn signed(self, request: &Request, secret: &str) -> bool {
+        if self == Forge::GitLab {
+            return request.header("X-Gitlab-Token").is_some_and(|token| same_secret(secret, token));
+        }
+        // Bitbucket signs into X-Hub-Signature, where GitHub keeps its legacy SHA-1
+        let signature = request
+            .header("X-Hub-Signature-256")
+            .or_else(|| request.header("X-Hub-Signature"))
+            .and_then(|value| value.strip_prefix("sha256="))
+            .or_else(|| request.header("X-Forgejo-Signature"))
+            .or_else(|| request.header("X-Gitea-Signature"));
+        let Some(signature) = signature.and_then(decode_hex) else {
+            return false;
+        };
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+            return false;
+        };
+        mac.update(request.body.as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+/// The parts of a push event used to find the repos to check. GitHub and
+/// Gitea name the repo in `repository.full_name`, GitLab in `project`.
+#[derive(Deserialize)]
+struct PushEvent {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    #[serde(default)]
+    deleted: bool,
+    /// All zeros when the ref was deleted
+    #[serde(default)]
+    after: String,
+    repository: Option<PushRepository>,
+    project: Option<GitlabProject>,
+}
+
+#[derive(Deserialize)]
+struct PushRepository {
+    full_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitlabProject {
+    path_with_namespace: String,
+}
+
+impl PushEvent {
+    fn into_push(self) -> Push {
+        let deleted = self.deleted || (!self.after.is_empty() && self.after.bytes().all(|b| b == b'0'));
+        let repository = match self.project {
+            Some(project) => Some(project.path_with_namespace),
+            None => self.repository.and_then(|repository| repository.full_name),
+        };
+        Push { repository, refs: if deleted { Vec::new() } else { vec![self.git_ref] } }
+    }
+}
+
+/// Bitbucket Cloud's `repo:push` (changes under `push`) and Bitbucket
+/// Server's `repo:refs_changed` (changes at the top level). One delivery
+/// may move several refs.
+#[derive(Deserialize)]
+struct BitbucketEvent {
+    repository: BitbucketRepository,
+    push: Option<BitbucketPush>,
+    #[serde(default)]
+    changes: Vec<BitbucketServerChange>,
+}
+
+/// Cloud names the repo in `full_name`, Server by its project key and slug
+#[derive(Deserialize)]
+struct BitbucketRepository {
+    full_name: Option<String>,
+    slug: Option<String>,
+    project: Option<BitbucketProject>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketProject {
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct BitbucketPush {
+    changes: Vec<BitbucketCloudChange>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketCloudChange {
+    /// Null when the ref was deleted
+    new: Option<BitbucketCloudRef>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketCloudRef {
+    /// "branch" or "tag"
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct BitbucketServerChange {
+    #[serde(rename = "refId")]
+    ref_id: String,
+    /// "ADD", "UPDATE" or "DELETE"
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+impl BitbucketEvent {
+    fn into_push(self) -> Push {
+        let repository = match (self.repository.full_name, self.repository.slug, self.repository.project) {
+            (Some(full_name), _, _) => Some(full_name),
+            (None, Some(slug), Some(project)) => Some(format!("{}/{}", project.key, slug)),
+            _ => None,
+        };
+        let cloud = self.push.into_iter().flat_map(|push| push.changes).filter_map(|change| {
+            let new = change.new?;
+            match new.kind.as_str() {
+                "branch" => Some(format!("refs/heads/{}", new.name)),
+                "tag" => Some(format!("refs/tags/{}", new.name)),
+                _ => None,
+            }
+        });
+        let server = self.changes.into_iter().filter(|change| change.kind != "DELETE").map(|change| change.ref_id);
+        Push { repository, refs: cloud.chain(server).collect() }
+    }
+}
+
+/// The repo a delivery is about and the refs it moved, deletions left out
+struct Push {
+    repository: Option<String>,
+    refs: Vec<String>,
+}
+
+/// A watched repo as the listener matches it against deliveries
+struct Target {
+    name: String,
+    branch: Option<String>,
+    path: PathBuf,
+    /// The repo's own `name`, for the log
+    label: String,
+    secret: Option<String>,
+}
+
+/// The repos deliveries are matched against, replaced when the config is reloaded
+pub struct Targets {
+    default_secret: Option<String>,
+    targets: RwLock<Vec<Target>>,
+}
+
+impl Targets {
+    pub fn new(cfg: &WebhookCfg, repos: &[RepoCfg]) -> Targets {
+        let targets = Targets { default_secret: cfg.secret.clone(), targets: RwLock::default() };
+        targets.replace(repos);
+        targets
+    }
+
+    pub fn replace(&self, repos: &[RepoCfg]) {
+        let targets = repos
+            .iter()
+            .filter_map(|repo| match git_ops::remote_repo_name(repo) {
+                Some(name) => Some(Target {
+                    name,
+                    branch: repo.branch.clone(),
+                    path: repo.path.clone(),
+                    label: repo.name().into_owned(),
+                    secret: repo.webhook_secret.clone().or_else(|| self.default_secret.clone()),
+                }),
+                None => {
+                    warn!("Webhooks can't trigger {}: its remote has no repository name", repo.name());
+                    None
+                }
+            })
+            .collect();
+        *self.targets.write().unwrap() = targets;
+    }
+}
+
+/// Accept GitHub, GitLab, Gitea/Forgejo and Bitbucket push webhooks on `cfg.listen` and
+/// trigger a check of every repo whose remote and branch match the push
+pub async fn serve(cfg: WebhookCfg, targets: Arc<Targets>, triggers: Arc<Triggers>) -> Result<()> {
+    info!("Accepting push webhooks on {}{}", cfg.listen, cfg.path);
+    let (listen, tls) = (cfg.listen, cfg.tls.clone());
+    let allowlist = Arc::new(Allowlist::new(cfg.allow_from.clone(), cfg.allow_github_hooks));
+    let refreshed = allowlist.clone();
+    tokio::spawn(async move { refreshed.refresh_github().await });
+    let handler: Handler = Arc::new(move |request| {
+        // Checked before the payload is looked at; the signature check still applies
+        if !allowlist.allows(request.peer) {
+            warn!("Rejected webhook from {}: not in allow_from", request.peer);
+            return Response::text(403, "forbidden");
+        }
+        handle(&request, &cfg, &targets.targets.read().unwrap(), &triggers)
+    });
+    http::serve(listen, tls, handler).await
+}
+
+fn handle(request: &Request, cfg: &WebhookCfg, targets: &[Target], triggers: &Triggers) -> Response {
+    if request.path != cfg.path {
+        return Response::text(404, "not found");
+    }
+    if request.method != "POST" {
+        return Response::text(405, "method not allowed");
+    }
+    let Some((forge, event)) = Forge::detect(request) else {
+        return Response::text(400, "missing X-GitHub-Event, X-Gitlab-Event, X-Gitea-Event or X-Event-Key header");
+    };
+    if !forge.authorized(request, cfg) {
+        warn!("Rejected {:?} webhook without the configured secret", forge);
+        return Response::text(401, "unauthorized");
+    }
+    if !forge.is_push(event) {
+        // Bitbucket Server's "Test connection" sends diagnostics:ping
+        if event == "ping" || event == "diagnostics:ping" {
+            return Response::text(200, "pong");
+        }
+        return Response::text(200, &format!("ignored {event} event"));
+    }
+    let push = match forge.parse_push(&request.body) {
+        Ok(push) => push,
+        Err(e) => return Response::text(400, &format!("invalid push event: {e}")),
+    };
+    let Some(repository) = push.repository.as_deref() else {
+        return Response::text(400, "push event names no repository");
+    };
+    if push.refs.is_empty() {
+        return Response::text(200, "ignored ref deletion");
+    }
+
+    let name = repository.to_lowercase();
+    let refs = push.refs.join(", ");
+    let (mut triggered, mut rejected) = (0, 0);
+    for target in targets {
+        let same_repo = target.name == name || target.name.ends_with(&format!("/{name}"));
+        // Repos following the remote default or a ref glob take every push of their remote
+        let same_branch = match &target.branch {
+            Some(watched) => push.refs.iter().any(|pushed| pushed.strip_prefix("refs/heads/") == Some(watched.as_str())),
+            None => true,
+        };
+        if !same_repo || !same_branch {
+            continue;
+        }
+        if target.secret.as_deref().is_some_and(|secret| !forge.signed(request, secret)) {
+            warn!("Rejected {:?} push to {} for {}: bad or missing signature", forge, repository, target.label);
+            rejected += 1;
+            continue;
+        }
+        if triggers.trigger(&target.path, "webhook") {
+            info!("{:?} push to {} {}, checking {} now", forge, repository, refs, target.label);
+            triggered += 1;
+        }
+    }
+    if triggered == 0 && rejected > 0 {
+        return Response::text(401, "unauthorized");
+    }
+    if triggered == 0 {
+        debug!("No watched repo follows {} {}", repository, refs);
+        return Response::text(200, "no matching repo");
+    }
+    Response::text(202, &format!("checking {triggered} repo(s)"))
+}
+
+/// Compare secrets without stopping at the first differing byte
+fn same_secret(expected: &str, sent: &str) -> bool {
+    expected.len() == sent.len() && expected.bytes().zip(sent.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
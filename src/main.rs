@@ -1,27 +1,194 @@
 mod daemon;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use daemon::config::Config;
+use daemon::control;
+use daemon::duration::parse_duration;
+use daemon::errors::WatchError;
+use daemon::git_ops;
+use daemon::keygen;
+use daemon::migrate::{self, Source};
+use daemon::hooks;
+use daemon::repo_config::{OnChange, RepoCfg};
+use daemon::setup;
+use daemon::sidecar::{self, SidecarOptions};
+use daemon::terraform;
+use daemon::validate;
 use daemon::watcher;
-use log::{error, info};
+use log::{error, info, warn};
 
 /// Simple Git repo auto-updater.
 #[derive(Parser)]
 struct Cli {
-    /// Path to config TOML
+    /// Path to config TOML; repeat to layer files, later ones override earlier ones
     #[arg(short, long, default_value = "/etc/watcher.toml")]
-    config_file: String,
+    config_file: Vec<String>,
+
+    /// Refuse to start when the config has keys no setting reads (same as `strict_config = true`)
+    #[arg(long, env = "RUSTPDATER_STRICT_CONFIG")]
+    strict_config: bool,
+
+    #[command(flatten)]
+    git_sync: GitSyncArgs,
+
+    #[command(flatten)]
+    sidecar: SidecarArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a dedicated ed25519 deploy key for a repo and wire it into the config
+    Keygen {
+        /// Repo to generate the key for (its name, its path or the last component of it)
+        #[arg(long)]
+        repo: String,
+        /// Directory holding the managed keys
+        #[arg(long, default_value = "/etc/rustpdater/keys")]
+        key_dir: PathBuf,
+    },
+    /// Approve an update held back by `commit_rules`, or apply a held terraform plan
+    Approve {
+        /// Repo to approve (its name, its path or the last component of it)
+        repo: String,
+    },
+    /// Translate an existing updater setup into a watcher config printed on stdout
+    Migrate {
+        /// Kind of setup to read
+        #[arg(long, value_enum)]
+        from: Source,
+        /// git-sync env/args file, crontab file or webhookd hooks directory
+        input: PathBuf,
+    },
+    /// Run a repo's hook now, as an update would, and report how it exited
+    TestHook {
+        /// Repo whose hook to run (its name, its path or the last component of it)
+        repo: String,
+        /// Hook to run
+        #[arg(long, value_enum, default_value = "on-change")]
+        hook: HookKind,
+        /// RUSTPDATER_OLD_SHA to hand the hook [default: --new]
+        #[arg(long)]
+        old: Option<String>,
+        /// RUSTPDATER_NEW_SHA to hand the hook [default: the checkout's HEAD]
+        #[arg(long)]
+        new: Option<String>,
+    },
+    /// Rehearse the update pipeline: rewind a repo to an older commit, then update it from the remote
+    Simulate {
+        /// Repo to rehearse on (its name, its path or the last component of it)
+        repo: String,
+        /// Commit to start from
+        #[arg(long)]
+        from: String,
+        /// Move the checkout back to where it was afterwards
+        #[arg(long)]
+        restore: bool,
+    },
+    /// Interactively add repos to the config (the last `--config-file`) and install the systemd unit
+    Setup,
+    /// Check and update every repo once, then exit; non-zero when any check failed
+    RunOnce,
+    /// Ask the running daemon to check a repo now instead of at its next interval
+    Update {
+        /// Repo to check (its name, its path or the last component of it)
+        repo: String,
+    },
+    /// Check the config, every repo's checkout, remote credentials and branch without starting the watcher
+    Validate,
+}
+
+/// Hooks `test-hook` can run
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum HookKind {
+    PreUpdate,
+    OnChange,
+    OnFailure,
+}
+
+/// Settings for running as a Kubernetes sidecar next to the application container
+#[derive(clap::Args)]
+#[command(next_help_heading = "Kubernetes sidecar")]
+struct SidecarArgs {
+    /// Sync the single `--repo` until SIGTERM and serve liveness/readiness probes
+    #[arg(long, env = "RUSTPDATER_SIDECAR")]
+    sidecar: bool,
+    /// Sentinel file written after the first successful sync [default: rustpdater-ready in the checkout's git dir]
+    #[arg(long, env = "RUSTPDATER_READY_FILE")]
+    ready_file: Option<PathBuf>,
+    /// Address serving /livez and /readyz
+    #[arg(long, env = "RUSTPDATER_PROBE_LISTEN", default_value = "0.0.0.0:8081")]
+    probe_listen: SocketAddr,
+}
+
+/// Flags mirroring kubernetes/git-sync so existing pod specs can switch binaries
+/// without rewriting their manifests. Passing `--repo` replaces the config file.
+#[derive(clap::Args)]
+#[command(next_help_heading = "git-sync compatibility")]
+struct GitSyncArgs {
+    /// Remote repository URL to sync
+    #[arg(long, env = "GITSYNC_REPO")]
+    repo: Option<String>,
+    /// Branch or ref to track ("HEAD" follows the remote's default branch)
+    #[arg(long = "ref", env = "GITSYNC_REF", default_value = "HEAD")]
+    git_ref: String,
+    /// Time between syncs, e.g. "10s" or "1m"
+    #[arg(long, env = "GITSYNC_PERIOD", default_value = "10s")]
+    period: String,
+    /// Directory the repository is checked out into
+    #[arg(long, env = "GITSYNC_ROOT", default_value = "/tmp/git")]
+    root: PathBuf,
+    /// Command to run after every successful sync
+    #[arg(long, env = "GITSYNC_EXECHOOK_COMMAND")]
+    exechook_command: Option<String>,
+}
+
+impl GitSyncArgs {
+    /// Build a single-repo config from the flags, if `--repo` was given
+    fn to_config(&self) -> Result<Option<Config>, Box<dyn Error>> {
+        let Some(url) = &self.repo else {
+            return Ok(None);
+        };
+
+        let period = parse_duration(&self.period)
+            .ok_or_else(|| format!("invalid --period '{}'", self.period))?;
+
+        let mut repo = RepoCfg::new(self.root.clone());
+        repo.url = Some(url.clone());
+        repo.branch = Some(self.git_ref.clone());
+        repo.interval = period.as_secs().max(1);
+        repo.on_change = self.exechook_command.clone().map(OnChange::Shell);
+
+        Ok(Some(Config { repos: vec![repo], ..Config::default() }))
+    }
 }
 
 #[tokio::main]
 async fn main() {
+    if git_ops::answer_askpass() {
+        return;
+    }
+
     // Initialize env_logger if not already set
     if std::env::var_os("RUST_LOG").is_none() {
         // Default to info if the user didn't set a level
         std::env::set_var("RUST_LOG", "info");
     }
-    env_logger::init();
+    // People at a terminal get short, colored lines; journald and pipes keep the full format
+    let mut logger = env_logger::Builder::from_default_env();
+    if std::io::stderr().is_terminal() {
+        logger.format(|buf, record| {
+            let style = buf.default_level_style(record.level());
+            writeln!(buf, "{} {style}{:<5}{style:#} {}", jiff::Zoned::now().strftime("%H:%M:%S"), record.level(), record.args())
+        });
+    }
+    logger.init();
 
     // Parse CLI arguments (We only need the config file path)
     let args = Cli::parse();
@@ -34,13 +201,141 @@ async fn main() {
 }
 
 async fn run(args: Cli) -> Result<(), Box<dyn Error>> {
-    // Load the config file
-    let config = Config::load_config(&args.config_file)?;
+    match &args.command {
+        Some(Command::Keygen { repo, key_dir }) => {
+            let config = Config::load_config(&args.config_file, args.strict_config)?;
+            let public_key = keygen::generate_deploy_key(&args.config_file, &config, repo, key_dir)?;
+            println!("Add this deploy key (read-only) to the repository on your git provider:\n");
+            println!("{public_key}");
+            return Ok(());
+        }
+        Some(Command::Approve { repo }) => {
+            let config = Config::load_config(&args.config_file, args.strict_config)?;
+            let repo_cfg = config.find_repo(repo).ok_or_else(|| WatchError::UnknownRepo(repo.clone()))?;
+            if let Some(terraform) = &repo_cfg.terraform {
+                if terraform::apply_held_plan(repo_cfg, terraform)? {
+                    println!("Applied the held {} plan for {}", terraform.binary, repo_cfg.name());
+                    return Ok(());
+                }
+            }
+            let sha = git_ops::approve_held_update(repo_cfg)?;
+            println!("Approved {sha} for {}; it is deployed on the next check", repo_cfg.name());
+            return Ok(());
+        }
+        Some(Command::Migrate { from, input }) => {
+            print!("{}", migrate::migrate(*from, input)?);
+            return Ok(());
+        }
+        Some(Command::TestHook { repo, hook, old, new }) => {
+            let config = Config::load_config(&args.config_file, args.strict_config)?;
+            let repo_cfg = config.find_repo(repo).ok_or_else(|| WatchError::UnknownRepo(repo.clone()))?;
+            let new = match new {
+                Some(new) => new.clone(),
+                None => git_ops::current_head(&repo_cfg.path)?,
+            };
+            let old = old.clone().unwrap_or_else(|| new.clone());
+            let mut vars = git_ops::hook_vars(repo_cfg, repo_cfg.branch(), &old, &new);
+            match hook {
+                HookKind::PreUpdate => {
+                    let pre_update = repo_cfg.pre_update.as_ref().ok_or_else(|| format!("{} has no pre_update hook", repo_cfg.name()))?;
+                    hooks::run_pre_update(repo_cfg, pre_update, &vars)?;
+                }
+                HookKind::OnChange => {
+                    let on_change = repo_cfg.on_change.as_ref().ok_or_else(|| format!("{} has no on_change hook", repo_cfg.name()))?;
+                    hooks::run_on_change(repo_cfg, on_change, &vars)?;
+                }
+                HookKind::OnFailure => {
+                    let on_failure = repo_cfg.on_failure.as_ref().ok_or_else(|| format!("{} has no on_failure hook", repo_cfg.name()))?;
+                    vars.push(("RUSTPDATER_ERROR", "test-hook run".to_string()));
+                    hooks::run_on_failure(repo_cfg, on_failure, &vars)?;
+                }
+            }
+            println!("Hook finished successfully");
+            return Ok(());
+        }
+        Some(Command::Simulate { repo, from, restore }) => {
+            let config = Config::load_config(&args.config_file, args.strict_config)?;
+            let repo_cfg = config.find_repo(repo).ok_or_else(|| WatchError::UnknownRepo(repo.clone()))?;
+            let outcome = git_ops::simulate(repo_cfg, from, *restore)?;
+            match outcome.update {
+                Some(event) => println!("Rehearsed {}: {} -> {} ({})", repo_cfg.name(), event.old_sha, event.new_sha, event.diffstat),
+                None => println!("No update was applied to {} (held back, locked or already current)", repo_cfg.name()),
+            }
+            return Ok(());
+        }
+        Some(Command::Setup) => {
+            let config_path = args.config_file.last().ok_or("no --config-file given")?;
+            setup::run(config_path.as_ref())?;
+            return Ok(());
+        }
+        Some(Command::Update { repo }) => {
+            let config = Config::load_config(&args.config_file, args.strict_config)?;
+            let socket = config.control_socket().ok_or("`control_socket` is disabled in the config")?;
+            // The daemon only knows paths; names are resolved here
+            let target = config.find_repo(repo).map_or_else(|| repo.clone(), |repo| repo.path.display().to_string());
+            println!("{}", control::send(socket, &format!("update {target}"))?);
+            return Ok(());
+        }
+        Some(Command::Validate) => {
+            let config = Config::load_config(&args.config_file, args.strict_config)?;
+            let problems = validate::run(&config);
+            if problems > 0 {
+                return Err(format!("{problems} problem(s) found in {}", args.config_file.join(", ")).into());
+            }
+            println!("{} is valid ({} repos)", args.config_file.join(", "), config.repos.len());
+            return Ok(());
+        }
+        Some(Command::RunOnce) => {
+            let config = match args.git_sync.to_config()? {
+                Some(config) => config,
+                None => Config::load_config(&args.config_file, args.strict_config)?,
+            };
+            for warning in &config.warnings {
+                warn!("Config: {}", warning);
+            }
+            let failed = watcher::run_once(&config);
+            if !failed.is_empty() {
+                return Err(format!("{} of {} repos failed: {}", failed.len(), config.repos.len(), failed.join(", ")).into());
+            }
+            info!("Checked {} repos", config.repos.len());
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if args.sidecar.sidecar {
+        let mut config = args.git_sync.to_config()?.ok_or("--sidecar requires --repo (or GITSYNC_REPO)")?;
+        let repo = config.repos.remove(0);
+        // Resolved inside the git dir once the checkout exists, a worktree's `.git` being a file
+        let ready_file = match args.sidecar.ready_file {
+            Some(file) => std::path::absolute(file)?,
+            None => PathBuf::from("rustpdater-ready"),
+        };
+
+        info!("Running as sidecar for {} (probes on {})", repo.name(), args.sidecar.probe_listen);
+        sidecar::run(repo, SidecarOptions { ready_file, probe_listen: args.sidecar.probe_listen }).await?;
+        return Ok(());
+    }
 
-    info!("Loaded config from {} ({} repos)", args.config_file, config.repos.len());
+    // Load the config file, unless git-sync style flags describe the repo directly
+    let load = || -> daemon::errors::Result<Config> {
+        let config = Config::load_config(&args.config_file, args.strict_config)?;
+        info!("Loaded config from {} ({} repos)", args.config_file.join(", "), config.repos.len());
+        for warning in &config.warnings {
+            warn!("Config: {}", warning);
+        }
+        Ok(config)
+    };
+    let (config, reload) = match args.git_sync.to_config()? {
+        Some(config) => {
+            info!("Running in git-sync compatibility mode for {}", args.git_sync.root.display());
+            (config, None)
+        }
+        None => (load()?, Some(&load as &dyn Fn() -> daemon::errors::Result<Config>)),
+    };
 
     // Start the daemon
-    watcher::start_watching_repos(&config.repos).await?;
+    watcher::start_watching_repos(&config, reload).await?;
 
     Ok(())
 }